@@ -1,13 +1,24 @@
-use actix_web::{get, web, HttpResponse, Responder};
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
 use chrono::prelude::*;
+use log::warn;
+use rand::{distributions::Alphanumeric, Rng};
 use serde::{Deserialize, Serialize};
 use serde_json;
+use serde_json::{Map, Value};
 
-use crate::actors;
+use crate::actors::{self, ActorProfile};
 use crate::app::AppState;
+use crate::authorized_fetch;
 use crate::config;
 use crate::constants::*;
-use crate::objects::ObjectNote;
+use crate::content::{escape_html, ProcessedContent};
+use crate::fetch;
+use crate::jsonld;
+use crate::model::{Context, OneOrMany, Reference};
+use crate::objects::{self, ObjectDocument, ObjectKind, ObjectNote, ObjectTombstone, Poll};
+use crate::remote_actors;
+use crate::sanitize;
+use crate::store::{Interaction, StoredObject};
 
 #[derive(Deserialize)]
 pub struct ActivityCreateNoteServicePathInfo {
@@ -18,56 +29,663 @@ pub struct ActivityCreateNoteServicePathInfo {
 #[get("/@{actor_name}/activities/{activity_id}.json")]
 pub async fn activities_service(
     path: web::Path<ActivityCreateNoteServicePathInfo>,
+    req: HttpRequest,
+    data: web::Data<AppState>,
 ) -> impl Responder {
-    let actor = actors::actor_lookup(&path.actor_name);
-    if let Err(_) = actor {
-        return HttpResponse::NotFound().finish();
+    if let Err(err) = authorized_fetch::authorize(&data, &req).await {
+        return err.response();
     }
-    let actor = actor.unwrap();
+    let actor = match actors::actor_lookup(&data, &path.actor_name) {
+        Err(actors::ResolverError::NotFound) => return HttpResponse::NotFound().finish(),
+        Err(actors::ResolverError::Gone(_)) => return HttpResponse::Gone().finish(),
+        Ok(actor) => actor,
+    };
 
-    match activity_lookup(&actor, &path.activity_id) {
-        Err(_err) => HttpResponse::NotFound().finish(),
-        Ok(result) => HttpResponse::Ok().body(serde_json::to_string_pretty(&result).unwrap()),
+    match activity_lookup(&data, &actor, &path.activity_id) {
+        Err(LookupError::NotFound) => HttpResponse::NotFound().finish(),
+        Err(LookupError::Gone) => HttpResponse::Gone().finish(),
+        Ok(result) => jsonld::respond(HttpResponse::Ok(), MEDIA_TYPE_ACTIVITY_JSON, &result),
     }
 }
 
+/// Look up the Create activity for a local object. Activities share their id
+/// with the object they created.
 pub fn activity_lookup(
+    data: &AppState,
     actor: &actors::LocalActorPerson,
-    activity_id: &String,
-) -> Result<ActivityCreateNote, LookupError> {
-    Ok(ActivityCreateNote::new(&actor, &activity_id))
+    activity_id: &str,
+) -> Result<Activity<ObjectNote>, LookupError> {
+    match objects::any_lookup(data, actor, activity_id) {
+        Err(objects::LookupError::NotFound) => Err(LookupError::NotFound),
+        Err(objects::LookupError::Gone(_)) => Err(LookupError::Gone),
+        Ok(note) => Ok(create(actor, &actor.activity_url(activity_id), note)),
+    }
+}
+
+/// The Create activity for a local object, addressed like the object.
+fn create(actor: &actors::LocalActorPerson, id: &str, object: ObjectNote) -> Activity<ObjectNote> {
+    let mut activity = Activity::new(actor, ACTIVITY_TYPE_CREATE, id, object);
+    activity.published = Some(activity.object.published);
+    activity.to = activity.object.to.clone();
+    activity.cc = activity.object.cc.clone();
+    activity
 }
 
 #[derive(Debug, PartialEq)]
 pub enum LookupError {
     NotFound,
+    Gone,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+/// An activity of any type, parameterised over its object. Properties we do
+/// not model are kept in `unknown`, so inbound activities survive being
+/// parsed and serialized again.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
-pub struct ActivityCreateNote {
-    #[serde(rename = "@context")]
-    pub context: String,
+pub struct Activity<T> {
+    /// Omitted on activities embedded in another one.
+    #[serde(rename = "@context", default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<Context>,
     pub id: String,
     #[serde(rename = "type")]
     pub activity_type: String,
-    pub actor: String,
-    pub object: ObjectNote,
-}
-
-impl ActivityCreateNote {
-    pub fn new(actor: &actors::LocalActorPerson, id: &String) -> Self {
-        ActivityCreateNote {
-            context: CONTEXT_ACTIVITYSTREAMS.to_string(),
-            id: id.clone(),
-            activity_type: ACTIVITY_TYPE_CREATE.to_string(),
-            actor: actor.actor_id(),
-            object: ObjectNote::new(
-                &"bar".to_string(),
-                &actor.actor_id(),
-                &"https://dev.mastodon.lmorchard.com/@lmorchard/109339034898409760".to_string(),
-                &"hello world".to_string(),
-            ),
+    pub actor: Reference<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub published: Option<DateTime<Utc>>,
+    pub object: T,
+    #[serde(default, skip_serializing_if = "OneOrMany::is_empty")]
+    pub to: OneOrMany<String>,
+    #[serde(default, skip_serializing_if = "OneOrMany::is_empty")]
+    pub cc: OneOrMany<String>,
+    #[serde(flatten)]
+    pub unknown: Map<String, Value>,
+}
+
+impl<T> Activity<T> {
+    pub fn new(actor: &actors::LocalActorPerson, activity_type: &str, id: &str, object: T) -> Self {
+        Activity {
+            context: Some(Value::from(CONTEXT_ACTIVITYSTREAMS).into()),
+            id: id.to_string(),
+            activity_type: activity_type.to_string(),
+            actor: Reference::Iri(actor.actor_id()),
+            published: None,
+            object,
+            to: OneOrMany::default(),
+            cc: OneOrMany::default(),
+            unknown: Map::new(),
+        }
+    }
+
+    /// Strip the `@context`, for embedding the activity in another one.
+    pub fn embedded(mut self) -> Self {
+        self.context = None;
+        self
+    }
+}
+
+/// An error that occured while interacting with an object.
+#[derive(Debug, PartialEq)]
+pub enum InteractionError {
+    /// The object is neither local nor could it be fetched.
+    NotFound,
+    /// The actor has already done this to the object.
+    AlreadyDone,
+    /// There is nothing to undo.
+    NotDone,
+}
+
+/// Generate a fresh identifier for a local object or activity.
+pub fn new_id() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(16)
+        .map(char::from)
+        .collect()
+}
+
+/// Everything needed to publish a new note, article or page.
+#[derive(Debug, Default)]
+pub struct Draft {
+    pub kind: ObjectKind,
+    pub in_reply_to: Option<String>,
+    pub content: ProcessedContent,
+    /// Title of an article or page
+    pub name: Option<String>,
+    pub summary: Option<String>,
+    pub attachment: Vec<ObjectDocument>,
+    pub poll: Option<Poll>,
+}
+
+/// Store a new object for `actor` and deliver its Create activity to the
+/// actor's followers, the actors it mentions and the author of the object it
+/// replies to. Replies join the thread of the object they reply to.
+/// Notes with a poll are published as a Question, which is closed once its
+/// end time passes, and articles and pages link to their HTML rendering.
+pub async fn publish_note(
+    data: &AppState,
+    actor: &actors::LocalActorPerson,
+    draft: Draft,
+) -> ObjectNote {
+    let kind = draft.kind;
+    let in_reply_to = draft.in_reply_to.as_deref();
+    let note_id = new_id();
+    let mut note = ObjectNote::new(
+        &actor.object_url(kind, &note_id),
+        &actor.actor_id(),
+        in_reply_to,
+        &sanitize::sanitize_html(&draft.content.html),
+    );
+    note.object_type = kind.object_type().to_string();
+    note.tag = draft.content.tags;
+    note.cc = draft.content.mentions.into();
+    note.attachment = draft.attachment;
+    note.name = draft.name;
+    note.summary = draft.summary.as_deref().map(escape_html);
+    if kind != ObjectKind::Note {
+        note.url = Some(actor.object_html_url(kind, &note_id));
+    }
+    let context = in_reply_to
+        .and_then(|parent_id| objects::object_value(data, parent_id))
+        .and_then(|parent| {
+            parent["context"]
+                .as_str()
+                .or_else(|| parent["conversation"].as_str())
+                .map(str::to_string)
+        })
+        .or_else(|| in_reply_to.map(str::to_string))
+        .unwrap_or_else(|| note.id.clone());
+    note.set_context(&context);
+    if let Some(poll) = &draft.poll {
+        note.set_poll(poll);
+        objects::tally_poll(data, &mut note);
+        actix_web::rt::spawn(close_poll(
+            data.clone(),
+            actor.name.clone(),
+            note.id.clone(),
+            poll.end_time,
+        ));
+    }
+    let addressees = addressee_inboxes(data, &mut note).await;
+    data.store.put_note(note.clone());
+
+    let activity = create(actor, &actor.activity_url(&note_id), note.clone());
+    let mut inboxes = data.store.follower_inboxes(&actor.name);
+    for inbox in addressees {
+        if !inboxes.contains(&inbox) {
+            inboxes.push(inbox);
+        }
+    }
+    data.delivery.enqueue(&data.store, actor, &inboxes, &activity);
+    data.store.add_recipients(&actor.name, &note.id, &inboxes);
+    if let Ok(activity) = serde_json::to_value(&activity) {
+        data.store.push_outbox(&actor.name, activity);
+    }
+
+    note
+}
+
+/// Inboxes of the remote actors a new object is addressed to besides the
+/// author's followers: those it mentions and the author of the object it
+/// replies to, who is added to `cc`. Actors that can't be resolved are
+/// skipped.
+async fn addressee_inboxes(data: &AppState, note: &mut ObjectNote) -> Vec<String> {
+    if let Some(parent_id) = note.in_reply_to.clone() {
+        match remote_author(data, &parent_id).await {
+            Ok(Some(author)) if !note.cc.contains(&author.id) => note.cc.push(author.id),
+            Ok(_) => (),
+            Err(err) => warn!("Could not resolve author of {}: {:?}", parent_id, err),
+        }
+    }
+    let mut inboxes = vec![];
+    for actor_id in note.cc.iter() {
+        if actors::local_actor_by_id(data, actor_id).is_some() {
+            continue;
         }
+        match remote_actors::remote_actor(data, actor_id).await {
+            Ok(actor) => {
+                let inbox = actor.delivery_inbox().to_string();
+                if !inboxes.contains(&inbox) {
+                    inboxes.push(inbox);
+                }
+            }
+            Err(err) => warn!("Could not resolve inbox of {}: {:?}", actor_id, err),
+        }
+    }
+    inboxes
+}
+
+/// Send the current state of a local poll to everyone who received it or
+/// voted in it.
+pub fn send_poll_update(data: &AppState, actor: &actors::LocalActorPerson, question_id: &str) {
+    let mut question = match data.store.get_object(question_id) {
+        Some(StoredObject::Note(question)) => *question,
+        _ => return,
+    };
+    objects::tally_poll(data, &mut question);
+    let mut activity = Activity::new(
+        actor,
+        ACTIVITY_TYPE_UPDATE,
+        &format!("{}#updates/{}", question.id, new_id()),
+        question,
+    );
+    activity.to = activity.object.to.clone();
+    activity.cc = activity.object.cc.clone();
+    let inboxes = data.store.recipients(question_id);
+    data.delivery.enqueue(&data.store, actor, &inboxes, &activity);
+}
+
+/// Wait for a poll to end, then close it and send out its final results.
+async fn close_poll(
+    data: AppState,
+    actor_name: String,
+    question_id: String,
+    end_time: DateTime<Utc>,
+) {
+    if let Ok(remaining) = (end_time - Utc::now()).to_std() {
+        actix_web::rt::time::sleep(remaining).await;
+    }
+    // The poll or its author may have been deleted in the meantime.
+    let actor = match actors::actor_lookup(&data, &actor_name) {
+        Err(_err) => return,
+        Ok(actor) => actor,
+    };
+    if data.store.close_poll(&question_id, Utc::now()) {
+        send_poll_update(&data, &actor, &question_id);
+    }
+}
+
+/// Replace a note with a tombstone and federate a Delete to every inbox that
+/// received the original.
+pub fn delete_note(
+    data: &AppState,
+    actor: &actors::LocalActorPerson,
+    note_id: &str,
+) -> Result<ObjectTombstone, objects::LookupError> {
+    let note = objects::any_lookup(data, actor, note_id)?;
+    let tombstone = data
+        .store
+        .tombstone_object(&note.id)
+        .ok_or(objects::LookupError::NotFound)?;
+
+    let mut activity = Activity::new(
+        actor,
+        ACTIVITY_TYPE_DELETE,
+        &actor.activity_url(&format!("{}-delete", note_id)),
+        tombstone.clone(),
+    );
+    activity.to = TO_PUBLIC.to_string().into();
+    let inboxes = data.store.recipients(&note.id);
+    data.delivery.enqueue(&data.store, actor, &inboxes, &activity);
+    data.store
+        .remove_outbox(&actor.name, &actor.activity_url(note_id));
+
+    Ok(tombstone)
+}
+
+/// Delete a local actor: leave a tombstone in its place, keep its name
+/// reserved, and federate a Delete to every inbox the actor is known to.
+pub fn delete_actor(
+    data: &AppState,
+    actor: &actors::LocalActorPerson,
+) -> Result<ObjectTombstone, actors::ResolverError> {
+    let tombstone = ObjectTombstone::new(&actor.actor_id(), &actor.actor_type);
+    if !data.store.delete_actor(&actor.name, tombstone.clone()) {
+        return Err(actors::ResolverError::NotFound);
+    }
+    let path = &config::CONFIG.deleted_actors_file;
+    if let Err(err) = actors::save_deleted_actors(&data.store, path) {
+        warn!("Could not save deleted actors: {}", err);
+    }
+
+    let mut activity = Activity::new(
+        actor,
+        ACTIVITY_TYPE_DELETE,
+        &format!("{}#delete", actor.actor_id()),
+        tombstone.clone(),
+    );
+    activity.to = TO_PUBLIC.to_string().into();
+    let inboxes = data.store.known_inboxes(&actor.name);
+    data.delivery.enqueue(&data.store, actor, &inboxes, &activity);
+
+    Ok(tombstone)
+}
+
+/// Replace a local actor's profile and send an Update with the new actor
+/// document to every inbox it has delivered to.
+pub fn update_profile(data: &AppState, actor: &actors::LocalActorPerson, profile: ActorProfile) {
+    data.store.set_profile(&actor.name, profile.clone());
+    let actor = actors::LocalActorPerson {
+        name: actor.name.clone(),
+        actor_type: actor.actor_type.clone(),
+        domain: actor.domain.clone(),
+        profile,
+    };
+    let mut activity = Activity::new(
+        &actor,
+        ACTIVITY_TYPE_UPDATE,
+        &format!("{}#updates/{}", actor.actor_id(), new_id()),
+        actor.to_object(),
+    );
+    activity.to = TO_PUBLIC.to_string().into();
+    let inboxes = data.store.known_inboxes(&actor.name);
+    data.delivery.enqueue(&data.store, &actor, &inboxes, &activity);
+}
+
+/// Like a local or remote object. Likes of remote objects are delivered to
+/// the object's author.
+pub async fn like(
+    data: &AppState,
+    actor: &actors::LocalActorPerson,
+    object_id: &str,
+) -> Result<Activity<String>, InteractionError> {
+    let author = remote_author(data, object_id).await?;
+    let activity = interact(data, actor, Interaction::Like, object_id)?;
+    if let Some(RemoteAuthor { inbox, .. }) = author {
+        data.delivery.enqueue(&data.store, actor, &[inbox], &activity);
+    }
+    Ok(activity)
+}
+
+/// Record an interaction of `actor` with an object, returning the activity
+/// that makes it. Fails if the actor has already interacted in that way.
+fn interact(
+    data: &AppState,
+    actor: &actors::LocalActorPerson,
+    interaction: Interaction,
+    object_id: &str,
+) -> Result<Activity<String>, InteractionError> {
+    let fragment = match interaction {
+        Interaction::Like => "likes",
+        Interaction::Announce => "announces",
+    };
+    let activity = Activity::new(
+        actor,
+        interaction.activity_type(),
+        &format!("{}#{}/{}", actor.actor_id(), fragment, new_id()),
+        object_id.to_string(),
+    );
+    if !data
+        .store
+        .add_interaction(interaction, object_id, &actor.actor_id(), &activity.id)
+    {
+        return Err(InteractionError::AlreadyDone);
+    }
+    Ok(activity)
+}
+
+/// Withdraw an interaction, returning the id of the activity that made it.
+fn uninteract(
+    data: &AppState,
+    actor: &actors::LocalActorPerson,
+    interaction: Interaction,
+    object_id: &str,
+) -> Result<String, InteractionError> {
+    data.store
+        .remove_interaction(interaction, object_id, &actor.actor_id())
+        .ok_or(InteractionError::NotDone)
+}
+
+/// Withdraw a like, delivering an Undo wherever the Like went.
+pub async fn unlike(
+    data: &AppState,
+    actor: &actors::LocalActorPerson,
+    object_id: &str,
+) -> Result<Activity<Activity<String>>, InteractionError> {
+    let like_id = uninteract(data, actor, Interaction::Like, object_id)?;
+    let like = Activity::new(actor, ACTIVITY_TYPE_LIKE, &like_id, object_id.to_string());
+    let activity = Activity::new(
+        actor,
+        ACTIVITY_TYPE_UNDO,
+        &format!("{}/undo", like_id),
+        like.embedded(),
+    );
+    if let Ok(Some(RemoteAuthor { inbox, .. })) = remote_author(data, object_id).await {
+        data.delivery.enqueue(&data.store, actor, &[inbox], &activity);
+    }
+    Ok(activity)
+}
+
+/// Announce a local or remote object to the actor's followers. Announces of
+/// remote objects are also delivered to the object's author.
+pub async fn announce(
+    data: &AppState,
+    actor: &actors::LocalActorPerson,
+    object_id: &str,
+) -> Result<Activity<String>, InteractionError> {
+    let author = remote_author(data, object_id).await?;
+    share(data, actor, object_id, author)
+}
+
+/// Record and deliver an Announce once we know who, if anyone, needs to hear
+/// about it besides the actor's followers.
+pub fn share(
+    data: &AppState,
+    actor: &actors::LocalActorPerson,
+    object_id: &str,
+    author: Option<RemoteAuthor>,
+) -> Result<Activity<String>, InteractionError> {
+    let mut activity = interact(data, actor, Interaction::Announce, object_id)?;
+    activity.published = Some(Utc::now());
+    activity.to = TO_PUBLIC.to_string().into();
+
+    let mut inboxes = data.store.follower_inboxes(&actor.name);
+    if let Some(author) = author {
+        activity.cc.push(author.id);
+        if !inboxes.contains(&author.inbox) {
+            inboxes.push(author.inbox);
+        }
+    }
+    data.delivery.enqueue(&data.store, actor, &inboxes, &activity);
+    data.store
+        .add_recipients(&actor.name, &activity.id, &inboxes);
+    if let Ok(activity) = serde_json::to_value(&activity) {
+        data.store.push_outbox(&actor.name, activity);
+    }
+    Ok(activity)
+}
+
+/// Withdraw an announce, delivering an Undo wherever the Announce went.
+pub fn unannounce(
+    data: &AppState,
+    actor: &actors::LocalActorPerson,
+    object_id: &str,
+) -> Result<Activity<Value>, InteractionError> {
+    let announce_id = uninteract(data, actor, Interaction::Announce, object_id)?;
+    let mut announce = data
+        .store
+        .remove_outbox(&actor.name, &announce_id)
+        .unwrap_or_else(|| Value::from(announce_id.clone()));
+    if let Some(announce) = announce.as_object_mut() {
+        announce.remove("@context");
+    }
+    let activity = Activity::new(
+        actor,
+        ACTIVITY_TYPE_UNDO,
+        &format!("{}/undo", announce_id),
+        announce,
+    );
+    let inboxes = data.store.recipients(&announce_id);
+    data.delivery.enqueue(&data.store, actor, &inboxes, &activity);
+    Ok(activity)
+}
+
+/// The author of a remote object, to be notified about interactions with it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemoteAuthor {
+    pub id: String,
+    pub inbox: String,
+}
+
+/// The author to notify about an interaction with an object, or `None` if the
+/// object is local and nobody needs notifying.
+async fn remote_author(
+    data: &AppState,
+    object_id: &str,
+) -> Result<Option<RemoteAuthor>, InteractionError> {
+    match data.store.get_object(object_id) {
+        Some(StoredObject::Note(_)) => return Ok(None),
+        Some(StoredObject::Tombstone(_)) => return Err(InteractionError::NotFound),
+        None => (),
+    }
+    let object = match data.store.get_remote_object(object_id) {
+        Some(object) => object,
+        None => fetch::fetch_object(object_id)
+            .await
+            .map_err(|_| InteractionError::NotFound)?,
+    };
+    let authors: OneOrMany<Reference<Value>> =
+        serde_json::from_value(object["attributedTo"].clone())
+            .map_err(|_| InteractionError::NotFound)?;
+    let author = authors
+        .first()
+        .and_then(Reference::id)
+        .ok_or(InteractionError::NotFound)?;
+    let author = remote_actors::remote_actor(data, author)
+        .await
+        .map_err(|_| InteractionError::NotFound)?;
+    Ok(Some(RemoteAuthor {
+        inbox: author.delivery_inbox().to_string(),
+        id: author.id,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delivery::DeliveryQueue;
+    use crate::remote_actors::RemoteActor;
+    use crate::store::Store;
+    use crate::webfinger::WebfingerClient;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn state() -> AppState {
+        AppState {
+            public_key: String::new(),
+            store: Arc::new(Store::new()),
+            delivery: DeliveryQueue::start(),
+            webfinger: Arc::new(WebfingerClient::default()),
+        }
+    }
+
+    fn local_actor(data: &AppState, name: &str, actor_type: &str) -> actors::LocalActorPerson {
+        let domain = &config::CONFIG.primary_domain().domain;
+        data.store.register_actor(name, actor_type, domain).unwrap();
+        actors::actor_lookup(data, name).unwrap()
+    }
+
+    fn put_remote_actor(data: &AppState, actor: Value) {
+        data.store
+            .put_remote_actor(RemoteActor::from_value(&actor, Utc::now()).unwrap());
+    }
+
+    #[actix_web::test]
+    async fn test_replies_and_mentions_are_delivered() {
+        let data = state();
+        let local = local_actor(&data, "a", ACTOR_TYPE_PERSON);
+        put_remote_actor(
+            &data,
+            json!({
+                "id": "https://one.example/users/b",
+                "type": "Person",
+                "inbox": "https://one.example/users/b/inbox",
+                "endpoints": {"sharedInbox": "https://one.example/inbox"},
+            }),
+        );
+        put_remote_actor(
+            &data,
+            json!({
+                "id": "https://two.example/users/c",
+                "type": "Person",
+                "inbox": "https://two.example/users/c/inbox",
+            }),
+        );
+        let parent_id = "https://one.example/notes/1";
+        data.store.put_remote_object(
+            parent_id,
+            json!({
+                "id": parent_id,
+                "type": "Note",
+                "attributedTo": "https://one.example/users/b",
+            }),
+        );
+
+        let mut note = ObjectNote::new(
+            &local.object_url(ObjectKind::Note, "1"),
+            &local.actor_id(),
+            Some(parent_id),
+            "<p>Hi</p>",
+        );
+        note.cc = vec![
+            "https://two.example/users/c".to_string(),
+            local.actor_id(),
+        ]
+        .into();
+        let inboxes = addressee_inboxes(&data, &mut note).await;
+        assert_eq!(
+            inboxes,
+            ["https://two.example/users/c/inbox", "https://one.example/inbox"]
+        );
+        assert!(note.cc.contains(&"https://one.example/users/b".to_string()));
+    }
+
+    #[actix_web::test]
+    async fn test_announce_and_undo_announce() {
+        let data = state();
+        let actor = local_actor(&data, "a", ACTOR_TYPE_PERSON);
+        let note = publish_note(&data, &actor, Draft::default()).await;
+
+        let announce = announce(&data, &actor, &note.id).await.unwrap();
+        assert_eq!(announce.activity_type, ACTIVITY_TYPE_ANNOUNCE);
+        assert_eq!(announce.object, note.id);
+        assert!(announce.to.contains(&TO_PUBLIC.to_string()));
+        assert_eq!(
+            super::announce(&data, &actor, &note.id).await,
+            Err(InteractionError::AlreadyDone)
+        );
+        assert_eq!(
+            data.store.interactions(Interaction::Announce, &note.id),
+            [actor.actor_id()]
+        );
+        let outbox = data.store.outbox("a");
+        assert_eq!(outbox.len(), 2);
+        assert_eq!(outbox[0]["id"], announce.id.as_str());
+        assert_eq!(outbox[1]["type"], ACTIVITY_TYPE_CREATE);
+
+        let undo = unannounce(&data, &actor, &note.id).unwrap();
+        assert_eq!(undo.activity_type, ACTIVITY_TYPE_UNDO);
+        assert_eq!(undo.object["id"], announce.id.as_str());
+        assert!(undo.object.get("@context").is_none());
+        assert!(data
+            .store
+            .interactions(Interaction::Announce, &note.id)
+            .is_empty());
+        assert_eq!(data.store.outbox("a").len(), 1);
+        assert_eq!(
+            unannounce(&data, &actor, &note.id).map(|_| ()),
+            Err(InteractionError::NotDone)
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_like_and_unlike() {
+        let data = state();
+        let actor = local_actor(&data, "a", ACTOR_TYPE_PERSON);
+        let note = publish_note(&data, &actor, Draft::default()).await;
+
+        let like = like(&data, &actor, &note.id).await.unwrap();
+        assert_eq!(like.activity_type, ACTIVITY_TYPE_LIKE);
+        assert_eq!(
+            super::like(&data, &actor, &note.id).await,
+            Err(InteractionError::AlreadyDone)
+        );
+        let undo = unlike(&data, &actor, &note.id).await.unwrap();
+        assert_eq!(undo.object.id, like.id);
+        assert_eq!(
+            unlike(&data, &actor, &note.id).await.map(|_| ()),
+            Err(InteractionError::NotDone)
+        );
+        // Likes are not published in the outbox.
+        assert_eq!(data.store.outbox("a").len(), 1);
     }
 }
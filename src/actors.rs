@@ -1,27 +1,163 @@
 use serde::{Deserialize, Serialize};
 use serde_json;
-use serde_json::{json, Value};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
 use std::fs;
+use std::io;
 
-use actix_web::{get, web, HttpResponse, Responder};
+use actix_web::http::header;
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
 
+use crate::app::AppState;
+use crate::authorized_fetch::{self, AuthorizationError};
 use crate::config;
 use crate::constants::*;
+use crate::html;
+use crate::jsonld;
+use crate::model::Context;
+use crate::objects::{self, ObjectImage, ObjectKind, ObjectPropertyValue, ObjectTombstone};
+use crate::store::{ActorStatus, Interaction, Store};
+
+/// Most name and value pairs a profile may show.
+pub const MAX_PROFILE_FIELDS: usize = 4;
 
 #[get("/@{name}/actor.json")]
-pub async fn actors_service(path: web::Path<String>) -> impl Responder {
+pub async fn actors_service(
+    path: web::Path<String>,
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> impl Responder {
     let name = path.into_inner();
-    match actor_lookup(&name) {
-        Err(_err) => HttpResponse::NotFound().finish(),
-        Ok(result) => match serde_json::to_string_pretty(&result.to_json_value()) {
-            Err(_err) => HttpResponse::NotFound().finish(),
-            Ok(body) => HttpResponse::Ok().body(body),
-        },
+    let authorization = authorized_fetch::authorize(&data, &req).await;
+    match actor_lookup(&data, &name) {
+        Err(ResolverError::NotFound) => HttpResponse::NotFound().finish(),
+        Err(ResolverError::Gone(tombstone)) => {
+            jsonld::respond(HttpResponse::Gone(), MEDIA_TYPE_ACTIVITY_JSON, &tombstone)
+        }
+        Ok(actor) => actor_document(&actor, MEDIA_TYPE_ACTIVITY_JSON, authorization),
+    }
+}
+
+/// The actor's ActivityPub representation. Requests that fail authorization
+/// still get its public key, or nobody could verify the signed fetches that
+/// secure mode demands.
+fn actor_document(
+    actor: &LocalActorPerson,
+    media_type: &str,
+    authorization: Result<(), AuthorizationError>,
+) -> HttpResponse {
+    match authorization {
+        Ok(()) => jsonld::respond(HttpResponse::Ok(), media_type, &actor.to_object()),
+        Err(AuthorizationError::Blocked) => AuthorizationError::Blocked.response(),
+        Err(_) => jsonld::respond(HttpResponse::Ok(), media_type, &actor.to_key_object()),
     }
 }
 
-pub fn actor_lookup(name: &String) -> Result<LocalActorPerson, ResolverError> {
-    Ok(LocalActorPerson::new(&name))
+#[derive(Deserialize)]
+pub struct ProfilePageParams {
+    page: Option<usize>,
+}
+
+/// The actor's id: its ActivityPub representation when asked for one through
+/// `Accept`, and its profile page otherwise.
+#[get("/@{name}")]
+pub async fn actor_page_service(
+    path: web::Path<String>,
+    query: web::Query<ProfilePageParams>,
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let name = path.into_inner();
+    let media_type = jsonld::negotiate(&req);
+    let authorization = match media_type {
+        Some(_) => authorized_fetch::authorize(&data, &req).await,
+        None => Ok(()),
+    };
+    let mut response = match (actor_lookup(&data, &name), media_type) {
+        (Err(ResolverError::NotFound), _) => HttpResponse::NotFound().finish(),
+        (Err(ResolverError::Gone(tombstone)), Some(media_type)) => {
+            jsonld::respond(HttpResponse::Gone(), media_type, &tombstone)
+        }
+        (Err(ResolverError::Gone(_)), None) => {
+            HttpResponse::Gone().content_type(MEDIA_TYPE_HTML).body(html::gone_page())
+        }
+        (Ok(actor), Some(media_type)) => actor_document(&actor, media_type, authorization),
+        (Ok(actor), None) => {
+            let page = query.page.unwrap_or_default();
+            let posts = objects::public_posts(&data, &actor);
+            // The first page always exists, even when empty; later ones only
+            // while there are posts left to show.
+            match page
+                .checked_mul(html::PAGE_SIZE)
+                .filter(|&start| page == 0 || start < posts.len())
+            {
+                None => HttpResponse::NotFound().finish(),
+                Some(start) => {
+                    let has_more = posts.len() - start.min(posts.len()) > html::PAGE_SIZE;
+                    let posts: Vec<_> =
+                        posts.into_iter().skip(start).take(html::PAGE_SIZE).collect();
+                    HttpResponse::Ok()
+                        .content_type(MEDIA_TYPE_HTML)
+                        .body(html::actor_page(&actor, &posts, page, has_more))
+                }
+            }
+        }
+    };
+    response.headers_mut().insert(header::VARY, header::HeaderValue::from_static("Accept"));
+    response
+}
+
+/// Write the tombstones of deleted actors to `path`, so their names stay
+/// reserved across restarts.
+pub fn save_deleted_actors(store: &Store, path: &str) -> io::Result<()> {
+    let json = serde_json::to_string_pretty(&store.deleted_actors()).map_err(io::Error::other)?;
+    let temporary = format!("{}.tmp", path);
+    fs::write(&temporary, json)?;
+    fs::rename(temporary, path)
+}
+
+/// Reserve the names of actors deleted in earlier runs, as saved by
+/// `save_deleted_actors`. A missing file means there are none.
+pub fn load_deleted_actors(store: &Store, path: &str) -> io::Result<()> {
+    let json = match fs::read_to_string(path) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        json => json?,
+    };
+    let deleted: BTreeMap<String, ObjectTombstone> =
+        serde_json::from_str(&json).map_err(io::Error::other)?;
+    for (name, tombstone) in deleted {
+        store.restore_deleted_actor(&name, tombstone);
+    }
+    Ok(())
+}
+
+pub fn actor_lookup(data: &AppState, name: &str) -> Result<LocalActorPerson, ResolverError> {
+    match data.store.actor_status(name) {
+        None => Err(ResolverError::NotFound),
+        Some(ActorStatus::Deleted(tombstone)) => Err(ResolverError::Gone(tombstone)),
+        Some(ActorStatus::Active { actor_type, domain }) => {
+            let mut actor = LocalActorPerson::new(name, &actor_type);
+            actor.domain = domain;
+            actor.profile = data.store.profile(name);
+            Ok(actor)
+        }
+    }
+}
+
+/// Find the active local actor with the given id, if it is one of ours. Ids
+/// in the older `/actor.json` form are still recognised.
+pub fn local_actor_by_id(data: &AppState, actor_id: &str) -> Option<LocalActorPerson> {
+    let actor_id = actor_id.strip_suffix("/actor.json").unwrap_or(actor_id);
+    let name = config::CONFIG
+        .hosted_domains
+        .iter()
+        .find_map(|hosted| actor_id.strip_prefix(&format!("{}/@", hosted.base_url)))?;
+    if name.contains('/') {
+        return None;
+    }
+    actor_lookup(data, name)
+        .ok()
+        .filter(|actor| actor.actor_id() == actor_id)
 }
 
 /// An error that occured while handling an incoming WebFinger request.
@@ -29,35 +165,111 @@ pub fn actor_lookup(name: &String) -> Result<LocalActorPerson, ResolverError> {
 pub enum ResolverError {
     /// The requested resource was not found.
     NotFound,
+    /// The actor has been deleted.
+    Gone(ObjectTombstone),
 }
 
 #[derive(Debug, Deserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct LocalActorPerson {
     pub name: String,
+    pub actor_type: String,
+    /// The hosted domain the actor's handle is under
+    pub domain: String,
+    #[serde(default)]
+    pub profile: ActorProfile,
+}
+
+/// What a local actor says about itself on its profile.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ActorProfile {
+    /// Bio, as HTML
+    pub summary: Option<String>,
+    /// Avatar
+    pub icon: Option<ObjectImage>,
+    pub fields: Vec<ObjectPropertyValue>,
 }
 
 impl LocalActorPerson {
-    pub fn new(name: &String) -> Self {
+    pub fn new(name: &str, actor_type: &str) -> Self {
         LocalActorPerson {
             name: name.to_string(),
+            actor_type: actor_type.to_string(),
+            domain: config::CONFIG.primary_domain().domain.clone(),
+            profile: ActorProfile::default(),
         }
     }
 
+    /// Groups rebroadcast whatever is addressed to them to their followers.
+    pub fn is_group(&self) -> bool {
+        self.actor_type == ACTOR_TYPE_GROUP
+    }
+
+    /// The hosted domain the actor belongs to, falling back to the primary
+    /// one if its domain is no longer configured.
+    pub fn hosted_domain(&self) -> &'static config::HostedDomain {
+        config::CONFIG
+            .hosted_domain(&self.domain)
+            .unwrap_or_else(|| config::CONFIG.primary_domain())
+    }
+
+    /// `user@domain`, without the leading `@`.
+    pub fn handle(&self) -> String {
+        format!("{}@{}", self.name, self.hosted_domain().domain)
+    }
+
     pub fn actor_base_url(&self) -> String {
-        format!("{}/@{}", config::CONFIG.base_url, &self.name)
+        format!("{}/@{}", self.hosted_domain().base_url, &self.name)
     }
 
     pub fn actor_html_url(&self) -> String {
-        format!("{}", self.actor_base_url())
+        self.actor_base_url()
     }
 
     pub fn actor_id(&self) -> String {
-        format!("{}/actor.json", self.actor_base_url())
+        self.actor_base_url()
+    }
+
+    pub fn key_id(&self) -> String {
+        format!("{}#main-key", self.actor_id())
+    }
+
+    pub fn note_url(&self, note_id: &str) -> String {
+        self.object_url(ObjectKind::Note, note_id)
+    }
+
+    pub fn object_url(&self, kind: ObjectKind, object_id: &str) -> String {
+        format!("{}/{}/{}", self.actor_base_url(), kind.collection(), object_id)
+    }
+
+    pub fn object_html_url(&self, kind: ObjectKind, object_id: &str) -> String {
+        self.object_url(kind, object_id)
+    }
+
+    pub fn replies_url(&self, kind: ObjectKind, object_id: &str) -> String {
+        format!("{}/replies.json", self.object_html_url(kind, object_id))
+    }
+
+    /// The collection of an object's likes or shares.
+    pub fn interactions_url(
+        &self,
+        kind: ObjectKind,
+        object_id: &str,
+        interaction: Interaction,
+    ) -> String {
+        format!(
+            "{}/{}.json",
+            self.object_html_url(kind, object_id),
+            interaction.collection()
+        )
+    }
+
+    pub fn activity_url(&self, activity_id: &str) -> String {
+        format!("{}/activities/{}.json", self.actor_base_url(), activity_id)
     }
 
     pub fn shared_inbox_url(&self) -> String {
-        format!("{}/inbox", config::CONFIG.base_url)
+        format!("{}/inbox", self.hosted_domain().base_url)
     }
 
     pub fn inbox_url(&self) -> String {
@@ -76,34 +288,175 @@ impl LocalActorPerson {
         fs::read_to_string("./private.pem").expect("Should be able to read public key")
     }
 
-    pub fn to_json_value(&self) -> Value {
-        json!({
-            "@context": [
-                CONTEXT_ACTIVITYSTREAMS.to_string(),
-                CONTEXT_SECURITY.to_string(),
-            ],
-            "id": self.actor_id(),
-            "type": ACTOR_TYPE_APPLICATION,
-            "preferredUsername": self.name,
-            "name": self.name,
-            "url": self.actor_html_url(),
-            "inbox": self.inbox_url(),
-            "outbox": self.outbox_url(),
-            /*
-            "icon": {
-                "type": "Image",
-                "mediaType": "image/png",
-                "url": "https://hackers.town/system/accounts/avatars/000/136/533/original/1a8c651efe14fcd6.png"
-            },            
-            */
-            "endpoints": {
-                "sharedInbox": self.shared_inbox_url(),
-            },
-            "publicKey": {
-                "id": format!("{}#main-key", &self.actor_id()),
-                "owner": self.actor_id(),
-                "publicKeyPem": self.public_key(),
-            }
-        })
+    pub fn to_object(&self) -> ObjectActor {
+        ObjectActor {
+            context: None,
+            id: self.actor_id(),
+            actor_type: self.actor_type.clone(),
+            preferred_username: Some(self.name.clone()),
+            name: Some(self.name.clone()),
+            url: Some(self.actor_html_url()),
+            summary: self.profile.summary.clone(),
+            icon: self.profile.icon.clone(),
+            attachment: self.profile.fields.clone(),
+            inbox: self.inbox_url(),
+            outbox: Some(self.outbox_url()),
+            manually_approves_followers: Some(false),
+            endpoints: Some(ActorEndpoints {
+                shared_inbox: Some(self.shared_inbox_url()),
+            }),
+            public_key: Some(ActorPublicKey {
+                id: self.key_id(),
+                owner: self.actor_id(),
+                public_key_pem: self.public_key(),
+            }),
+            unknown: Map::new(),
+        }
+    }
+
+    /// Just enough of the actor to verify its signatures with.
+    pub fn to_key_object(&self) -> ObjectActor {
+        ObjectActor {
+            context: None,
+            id: self.actor_id(),
+            actor_type: self.actor_type.clone(),
+            preferred_username: Some(self.name.clone()),
+            name: None,
+            url: None,
+            summary: None,
+            icon: None,
+            attachment: Vec::new(),
+            inbox: self.inbox_url(),
+            outbox: None,
+            manually_approves_followers: None,
+            endpoints: None,
+            public_key: Some(ActorPublicKey {
+                id: self.key_id(),
+                owner: self.actor_id(),
+                public_key_pem: self.public_key(),
+            }),
+            unknown: Map::new(),
+        }
+    }
+}
+
+/// An actor document, ours or one fetched from another server.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ObjectActor {
+    #[serde(rename = "@context", default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<Context>,
+    pub id: String,
+    #[serde(rename = "type")]
+    pub actor_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preferred_username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<ObjectImage>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachment: Vec<ObjectPropertyValue>,
+    pub inbox: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outbox: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manually_approves_followers: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoints: Option<ActorEndpoints>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<ActorPublicKey>,
+    #[serde(flatten)]
+    pub unknown: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ActorEndpoints {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shared_inbox: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ActorPublicKey {
+    pub id: String,
+    pub owner: String,
+    pub public_key_pem: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::activities::{self, Draft};
+    use crate::delivery::DeliveryQueue;
+    use crate::store::RegisterError;
+    use crate::webfinger::WebfingerClient;
+    use actix_web::test as actix_test;
+    use actix_web::App;
+    use std::sync::Arc;
+
+    #[actix_web::test]
+    async fn test_profile_pages_end_after_the_last_post() {
+        let data = AppState {
+            public_key: String::new(),
+            store: Arc::new(Store::new()),
+            delivery: DeliveryQueue::start(),
+            webfinger: Arc::new(WebfingerClient::default()),
+        };
+        let domain = &config::CONFIG.primary_domain().domain;
+        data.store.register_actor("a", ACTOR_TYPE_PERSON, domain).unwrap();
+        let actor = actor_lookup(&data, "a").unwrap();
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(web::Data::new(data.clone()))
+                .service(actor_page_service),
+        )
+        .await;
+        let status = |uri: &str| {
+            let request = actix_test::TestRequest::get().uri(uri).to_request();
+            let response = actix_test::call_service(&app, request);
+            async { response.await.status().as_u16() }
+        };
+
+        assert_eq!(status("/@a").await, 200);
+        assert_eq!(status("/@a?page=1").await, 404);
+        for _ in 0..=html::PAGE_SIZE {
+            activities::publish_note(&data, &actor, Draft::default()).await;
+        }
+        assert_eq!(status("/@a?page=1").await, 200);
+        assert_eq!(status("/@a?page=2").await, 404);
+        assert_eq!(status(&format!("/@a?page={}", usize::MAX)).await, 404);
+        assert_eq!(status(&format!("/@a?page={}", usize::MAX / 2)).await, 404);
+    }
+
+    #[test]
+    fn test_deleted_actors_survive_a_restart() {
+        let path = std::env::temp_dir().join(format!("deleted-actors-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let domain = &config::CONFIG.primary_domain().domain;
+        let store = Store::new();
+        store.register_actor("a", ACTOR_TYPE_PERSON, domain).unwrap();
+        store.register_actor("b", ACTOR_TYPE_PERSON, domain).unwrap();
+        let tombstone = ObjectTombstone::new("https://example.com/@a", ACTOR_TYPE_PERSON);
+        store.delete_actor("a", tombstone.clone());
+        save_deleted_actors(&store, path).unwrap();
+
+        let restarted = Store::new();
+        load_deleted_actors(&restarted, path).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(
+            restarted.register_actor("a", ACTOR_TYPE_PERSON, domain),
+            Err(RegisterError::Reserved)
+        );
+        assert_eq!(restarted.actor_status("a"), Some(ActorStatus::Deleted(tombstone)));
+        assert!(restarted.register_actor("b", ACTOR_TYPE_PERSON, domain).is_ok());
+
+        // Nothing saved yet is nothing deleted.
+        assert!(load_deleted_actors(&Store::new(), path).is_ok());
     }
 }
@@ -0,0 +1,115 @@
+extern crate dotenv;
+
+use std::fs;
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+use serde_json::json;
+
+use rust_activitypub_play::config::{self, CONFIG};
+
+/// Manage a running server through its admin API.
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Base URL of the server, defaulting to the configured one
+    #[arg(long)]
+    server: Option<String>,
+    /// Admin token, defaulting to ADMIN_TOKEN
+    #[arg(long)]
+    token: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Moderate remote domains
+    #[command(subcommand)]
+    Domains(DomainCommand),
+}
+
+#[derive(Subcommand)]
+enum DomainCommand {
+    /// List every domain policy
+    List,
+    /// Set the policy for a domain and its subdomains
+    Set {
+        domain: String,
+        /// noop, silence or suspend
+        #[arg(long, default_value = "suspend")]
+        severity: String,
+        /// Don't proxy or show media from the domain
+        #[arg(long)]
+        reject_media: bool,
+        #[arg(long)]
+        comment: Option<String>,
+    },
+    /// Remove the policy for a domain
+    Remove { domain: String },
+    /// Print every policy as CSV in Mastodon's domain block format
+    Export,
+    /// Import policies from a CSV file in Mastodon's domain block format
+    Import { file: PathBuf },
+    /// List the domains allowed in allowlist mode
+    Allowed,
+    /// Allow a domain in allowlist mode
+    Allow { domain: String },
+    /// Stop allowing a domain in allowlist mode
+    Disallow { domain: String },
+}
+
+#[actix_web::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    config::init();
+    let cli = Cli::parse();
+    let server = cli.server.unwrap_or_else(|| CONFIG.base_url.clone());
+    let token = cli
+        .token
+        .or_else(|| CONFIG.admin_token.clone())
+        .ok_or("No admin token: pass --token or set ADMIN_TOKEN")?;
+
+    let client = reqwest::Client::new();
+    let url = |path: &str| format!("{}/api/{}", server, path);
+    let request = match cli.command {
+        Command::Domains(DomainCommand::List) => client.get(url("domain_policies")),
+        Command::Domains(DomainCommand::Set {
+            domain,
+            severity,
+            reject_media,
+            comment,
+        }) => client
+            .put(url(&format!("domain_policies/{}", domain)))
+            .json(&json!({
+                "severity": severity,
+                "reject_media": reject_media,
+                "public_comment": comment,
+            })),
+        Command::Domains(DomainCommand::Remove { domain }) => {
+            client.delete(url(&format!("domain_policies/{}", domain)))
+        }
+        Command::Domains(DomainCommand::Export) => client.get(url("domain_policies.csv")),
+        Command::Domains(DomainCommand::Import { file }) => client
+            .post(url("domain_policies.csv"))
+            .header("content-type", "text/csv")
+            .body(fs::read(file)?),
+        Command::Domains(DomainCommand::Allowed) => client.get(url("domain_allows")),
+        Command::Domains(DomainCommand::Allow { domain }) => {
+            client.put(url(&format!("domain_allows/{}", domain)))
+        }
+        Command::Domains(DomainCommand::Disallow { domain }) => {
+            client.delete(url(&format!("domain_allows/{}", domain)))
+        }
+    };
+
+    let response = request.bearer_auth(token).send().await?;
+    let status = response.status();
+    let body = response.text().await?;
+    if !body.is_empty() {
+        println!("{}", body.trim_end());
+    }
+    if !status.is_success() {
+        return Err(format!("Server responded {}", status).into());
+    }
+    Ok(())
+}
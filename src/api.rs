@@ -0,0 +1,811 @@
+use actix_multipart::Multipart;
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse, Responder};
+use chrono::{Duration, Utc};
+use futures_util::StreamExt;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json;
+
+use crate::activities::{self, Draft, InteractionError};
+use crate::actors;
+use crate::app::AppState;
+use crate::authorized_fetch;
+use crate::config;
+use crate::constants::*;
+use crate::content;
+use crate::media::{self, MediaError};
+use crate::moderation::{self, DomainPolicy, Severity};
+use crate::objects::{
+    self, LookupError, ObjectDocument, ObjectImage, ObjectKind, ObjectPropertyValue, Poll,
+    MAX_POLL_DURATION, MAX_POLL_OPTIONS,
+};
+use crate::sanitize;
+use crate::store::RegisterError;
+
+lazy_static! {
+    /// Names local actors may register, as usernames are elsewhere in the
+    /// fediverse.
+    static ref ACTOR_NAME: Regex = Regex::new(r"^[A-Za-z0-9_]{1,30}$").unwrap();
+}
+
+/// Body accepted by the publish endpoint
+#[derive(Deserialize)]
+pub struct PublishNoteParams {
+    /// `Note` (the default), `Article` or `Page`
+    #[serde(rename = "type")]
+    object_type: Option<String>,
+    in_reply_to: Option<String>,
+    /// Title, required for articles and pages
+    name: Option<String>,
+    /// Plain text summary of an article or page
+    summary: Option<String>,
+    content: String,
+    /// Treat `content` as Markdown rather than plain text
+    #[serde(default)]
+    markdown: bool,
+    /// Media previously uploaded through the media endpoint
+    #[serde(default)]
+    media_ids: Vec<String>,
+    /// Publish the note as a poll
+    poll: Option<PollParams>,
+}
+
+/// Poll attached to a published note
+#[derive(Deserialize)]
+pub struct PollParams {
+    options: Vec<String>,
+    /// Let voters choose more than one option
+    #[serde(default)]
+    multiple: bool,
+    /// Seconds until the poll closes
+    expires_in: i64,
+}
+
+/// Body accepted by the actor registration endpoint
+#[derive(Deserialize)]
+pub struct RegisterActorParams {
+    name: String,
+    /// Register a Group, which rebroadcasts posts addressed to it
+    #[serde(default)]
+    group: bool,
+    /// Hosted domain for the actor's handle, the primary domain by default
+    domain: Option<String>,
+}
+
+/// Body accepted by the profile endpoint, replacing the whole profile
+#[derive(Deserialize)]
+pub struct ProfileParams {
+    /// Bio, as plain text
+    summary: Option<String>,
+    /// Treat `summary` as Markdown rather than plain text
+    #[serde(default)]
+    markdown: bool,
+    /// Image previously uploaded through the media endpoint
+    avatar: Option<String>,
+    #[serde(default)]
+    fields: Vec<ProfileFieldParams>,
+}
+
+#[derive(Deserialize)]
+pub struct ProfileFieldParams {
+    name: String,
+    value: String,
+}
+
+/// Body or query identifying the object of an interaction
+#[derive(Deserialize)]
+pub struct InteractionParams {
+    object: String,
+}
+
+#[derive(Deserialize)]
+pub struct NotePathInfo {
+    actor_name: String,
+    note_id: String,
+}
+
+#[post("/api/@{actor_name}/notes")]
+pub async fn publish_note_service(
+    req: HttpRequest,
+    path: web::Path<String>,
+    params: web::Json<PublishNoteParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if !is_authorized(&req) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    let actor = match actors::actor_lookup(&data, &path.into_inner()) {
+        Err(actors::ResolverError::NotFound) => return HttpResponse::NotFound().finish(),
+        Err(actors::ResolverError::Gone(_)) => return HttpResponse::Gone().finish(),
+        Ok(actor) => actor,
+    };
+
+    let (kind, name) = match kind_and_name(&params) {
+        Err(message) => return HttpResponse::BadRequest().body(message),
+        Ok(kind_and_name) => kind_and_name,
+    };
+
+    let mut attachment = vec![];
+    for media_id in &params.media_ids {
+        match data.store.get_media(media_id) {
+            None => return HttpResponse::BadRequest().body(format!("Unknown media {}", media_id)),
+            Some(document) => attachment.push(document),
+        }
+    }
+
+    let poll = match &params.poll {
+        None => None,
+        Some(_) if kind != ObjectKind::Note => {
+            return HttpResponse::BadRequest().body("Only notes can have a poll")
+        }
+        Some(poll) => match poll_from_params(poll) {
+            Err(message) => return HttpResponse::BadRequest().body(message),
+            Ok(poll) => Some(poll),
+        },
+    };
+
+    let base_url = &actor.hosted_domain().base_url;
+    let content = content::process(&data, base_url, &params.content, params.markdown).await;
+    let note = activities::publish_note(
+        &data,
+        &actor,
+        Draft {
+            kind,
+            in_reply_to: params.in_reply_to.clone(),
+            content,
+            name,
+            summary: params.summary.clone(),
+            attachment,
+            poll,
+        },
+    )
+    .await;
+    HttpResponse::Created().body(serde_json::to_string_pretty(&note).unwrap())
+}
+
+/// The kind of object to publish and its title, which articles and pages
+/// can't do without.
+fn kind_and_name(params: &PublishNoteParams) -> Result<(ObjectKind, Option<String>), String> {
+    let kind = match params.object_type.as_deref() {
+        None => ObjectKind::Note,
+        Some(object_type) => ObjectKind::from_object_type(object_type)
+            .ok_or_else(|| format!("Cannot publish objects of type {}", object_type))?,
+    };
+    let name = params
+        .name
+        .as_deref()
+        .map(str::trim)
+        .filter(|name| !name.is_empty());
+    if kind != ObjectKind::Note && name.is_none() {
+        return Err("Articles and pages need a name".to_string());
+    }
+    Ok((kind, name.map(str::to_string)))
+}
+
+fn poll_from_params(params: &PollParams) -> Result<Poll, String> {
+    let mut options: Vec<String> = vec![];
+    for option in &params.options {
+        let option = option.trim();
+        if option.is_empty() || options.iter().any(|existing| existing == option) {
+            return Err("Poll options must be distinct and not empty".to_string());
+        }
+        options.push(option.to_string());
+    }
+    if !(2..=MAX_POLL_OPTIONS).contains(&options.len()) {
+        return Err(format!(
+            "Polls need between 2 and {} options",
+            MAX_POLL_OPTIONS
+        ));
+    }
+    if !(1..=MAX_POLL_DURATION).contains(&params.expires_in) {
+        return Err(format!(
+            "Polls must expire within {} seconds",
+            MAX_POLL_DURATION
+        ));
+    }
+    let end_time = Utc::now()
+        .checked_add_signed(Duration::seconds(params.expires_in))
+        .ok_or("Polls must expire within a representable time")?;
+    Ok(Poll {
+        options,
+        multiple: params.multiple,
+        end_time,
+    })
+}
+
+#[delete("/api/@{actor_name}/notes/{note_id}")]
+pub async fn delete_note_service(
+    req: HttpRequest,
+    path: web::Path<NotePathInfo>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if !is_authorized(&req) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    let actor = match actors::actor_lookup(&data, &path.actor_name) {
+        Err(actors::ResolverError::NotFound) => return HttpResponse::NotFound().finish(),
+        Err(actors::ResolverError::Gone(_)) => return HttpResponse::Gone().finish(),
+        Ok(actor) => actor,
+    };
+
+    match activities::delete_note(&data, &actor, &path.note_id) {
+        Err(LookupError::NotFound) => HttpResponse::NotFound().finish(),
+        Err(LookupError::Gone(_)) => HttpResponse::Gone().finish(),
+        Ok(tombstone) => HttpResponse::Ok().body(serde_json::to_string_pretty(&tombstone).unwrap()),
+    }
+}
+
+/// Body returned by the media endpoint: the attachment, with the id to pass
+/// in `media_ids` when publishing
+#[derive(Serialize)]
+struct UploadedMedia {
+    id: String,
+    #[serde(flatten)]
+    document: ObjectDocument,
+}
+
+/// Accepts a multipart form with a `file` field and an optional
+/// `description` field holding alt text.
+#[post("/api/@{actor_name}/media")]
+pub async fn upload_media_service(
+    req: HttpRequest,
+    path: web::Path<String>,
+    mut payload: Multipart,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if !is_authorized(&req) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    if let Err(err) = actors::actor_lookup(&data, &path.into_inner()) {
+        return match err {
+            actors::ResolverError::NotFound => HttpResponse::NotFound().finish(),
+            actors::ResolverError::Gone(_) => HttpResponse::Gone().finish(),
+        };
+    }
+
+    let mut file: Option<(String, Vec<u8>)> = None;
+    let mut description: Option<String> = None;
+    while let Some(field) = payload.next().await {
+        let mut field = match field {
+            Err(_err) => return HttpResponse::BadRequest().finish(),
+            Ok(field) => field,
+        };
+        let name = field.name().to_string();
+        let media_type = field.content_type().essence_str().to_string();
+
+        let mut bytes = Vec::new();
+        while let Some(chunk) = field.next().await {
+            match chunk {
+                Err(_err) => return HttpResponse::BadRequest().finish(),
+                Ok(chunk) => bytes.extend_from_slice(&chunk),
+            }
+            if bytes.len() > config::CONFIG.max_upload_size {
+                return HttpResponse::PayloadTooLarge().finish();
+            }
+        }
+
+        if name == "file" {
+            file = Some((media_type, bytes));
+        } else if name == "description" {
+            description = String::from_utf8(bytes).ok();
+        }
+    }
+
+    let (media_type, bytes) = match file {
+        None => return HttpResponse::BadRequest().body("Missing file field"),
+        Some(file) => file,
+    };
+    match media::store_media(&data, &bytes, &media_type, description.as_deref()).await {
+        Err(MediaError::Empty) => HttpResponse::BadRequest().body("Empty file"),
+        Err(MediaError::TooLarge) => HttpResponse::PayloadTooLarge().finish(),
+        Err(MediaError::UnsupportedType) => HttpResponse::UnsupportedMediaType().finish(),
+        Err(MediaError::Image(_)) => HttpResponse::UnprocessableEntity().finish(),
+        Err(MediaError::Io(_)) | Err(MediaError::FetchFailed) => {
+            HttpResponse::InternalServerError().finish()
+        }
+        Ok(document) => {
+            let media = UploadedMedia {
+                id: media::media_id(&document.url),
+                document,
+            };
+            HttpResponse::Created().body(serde_json::to_string_pretty(&media).unwrap())
+        }
+    }
+}
+
+#[post("/api/@{actor_name}/likes")]
+pub async fn like_service(
+    req: HttpRequest,
+    path: web::Path<String>,
+    params: web::Json<InteractionParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if !is_authorized(&req) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    let actor = match actors::actor_lookup(&data, &path.into_inner()) {
+        Err(actors::ResolverError::NotFound) => return HttpResponse::NotFound().finish(),
+        Err(actors::ResolverError::Gone(_)) => return HttpResponse::Gone().finish(),
+        Ok(actor) => actor,
+    };
+
+    match activities::like(&data, &actor, &params.object).await {
+        Err(err) => interaction_error(err),
+        Ok(activity) => {
+            HttpResponse::Created().body(serde_json::to_string_pretty(&activity).unwrap())
+        }
+    }
+}
+
+#[delete("/api/@{actor_name}/likes")]
+pub async fn unlike_service(
+    req: HttpRequest,
+    path: web::Path<String>,
+    params: web::Query<InteractionParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if !is_authorized(&req) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    let actor = match actors::actor_lookup(&data, &path.into_inner()) {
+        Err(actors::ResolverError::NotFound) => return HttpResponse::NotFound().finish(),
+        Err(actors::ResolverError::Gone(_)) => return HttpResponse::Gone().finish(),
+        Ok(actor) => actor,
+    };
+
+    match activities::unlike(&data, &actor, &params.object).await {
+        Err(err) => interaction_error(err),
+        Ok(activity) => HttpResponse::Ok().body(serde_json::to_string_pretty(&activity).unwrap()),
+    }
+}
+
+#[post("/api/@{actor_name}/announces")]
+pub async fn announce_service(
+    req: HttpRequest,
+    path: web::Path<String>,
+    params: web::Json<InteractionParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if !is_authorized(&req) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    let actor = match actors::actor_lookup(&data, &path.into_inner()) {
+        Err(actors::ResolverError::NotFound) => return HttpResponse::NotFound().finish(),
+        Err(actors::ResolverError::Gone(_)) => return HttpResponse::Gone().finish(),
+        Ok(actor) => actor,
+    };
+
+    match activities::announce(&data, &actor, &params.object).await {
+        Err(err) => interaction_error(err),
+        Ok(activity) => {
+            HttpResponse::Created().body(serde_json::to_string_pretty(&activity).unwrap())
+        }
+    }
+}
+
+#[delete("/api/@{actor_name}/announces")]
+pub async fn unannounce_service(
+    req: HttpRequest,
+    path: web::Path<String>,
+    params: web::Query<InteractionParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if !is_authorized(&req) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    let actor = match actors::actor_lookup(&data, &path.into_inner()) {
+        Err(actors::ResolverError::NotFound) => return HttpResponse::NotFound().finish(),
+        Err(actors::ResolverError::Gone(_)) => return HttpResponse::Gone().finish(),
+        Ok(actor) => actor,
+    };
+
+    match activities::unannounce(&data, &actor, &params.object) {
+        Err(err) => interaction_error(err),
+        Ok(activity) => HttpResponse::Ok().body(serde_json::to_string_pretty(&activity).unwrap()),
+    }
+}
+
+fn interaction_error(err: InteractionError) -> HttpResponse {
+    match err {
+        InteractionError::NotFound => HttpResponse::NotFound().finish(),
+        InteractionError::AlreadyDone | InteractionError::NotDone => {
+            HttpResponse::Conflict().finish()
+        }
+    }
+}
+
+/// Threads only contain public notes, so this needs no token. Like any
+/// other fetch, it is refused to rejected signers and, in secure mode, to
+/// unsigned requests.
+#[get("/api/@{actor_name}/notes/{note_id}/thread")]
+pub async fn thread_service(
+    req: HttpRequest,
+    path: web::Path<NotePathInfo>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if let Err(err) = authorized_fetch::authorize(&data, &req).await {
+        return err.response();
+    }
+    let actor = match actors::actor_lookup(&data, &path.actor_name) {
+        Err(actors::ResolverError::NotFound) => return HttpResponse::NotFound().finish(),
+        Err(actors::ResolverError::Gone(_)) => return HttpResponse::Gone().finish(),
+        Ok(actor) => actor,
+    };
+
+    let note = match objects::any_lookup(&data, &actor, &path.note_id) {
+        Err(LookupError::NotFound) => return HttpResponse::NotFound().finish(),
+        Err(LookupError::Gone(_)) => return HttpResponse::Gone().finish(),
+        Ok(note) => note,
+    };
+    match objects::thread_tree(&data, &note.id) {
+        None => HttpResponse::NotFound().finish(),
+        Some(thread) => HttpResponse::Ok().body(serde_json::to_string_pretty(&thread).unwrap()),
+    }
+}
+
+#[post("/api/actors")]
+pub async fn register_actor_service(
+    req: HttpRequest,
+    params: web::Json<RegisterActorParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if !is_authorized(&req) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    if !ACTOR_NAME.is_match(&params.name) {
+        return HttpResponse::BadRequest().body("Names are 1 to 30 letters, digits or underscores");
+    }
+    let actor_type = if params.group {
+        ACTOR_TYPE_GROUP
+    } else {
+        ACTOR_TYPE_APPLICATION
+    };
+    let hosted = match &params.domain {
+        None => config::CONFIG.primary_domain(),
+        Some(domain) => match config::CONFIG.hosted_domain(domain) {
+            None => {
+                return HttpResponse::BadRequest().body(format!("{} is not hosted here", domain))
+            }
+            Some(hosted) => hosted,
+        },
+    };
+    match data
+        .store
+        .register_actor(&params.name, actor_type, &hosted.domain)
+    {
+        Err(RegisterError::Taken) | Err(RegisterError::Reserved) => {
+            HttpResponse::Conflict().finish()
+        }
+        Ok(()) => {
+            let actor = actors::LocalActorPerson {
+                domain: hosted.domain.clone(),
+                ..actors::LocalActorPerson::new(&params.name, actor_type)
+            };
+            HttpResponse::Created().body(serde_json::to_string_pretty(&actor.to_object()).unwrap())
+        }
+    }
+}
+
+#[put("/api/@{actor_name}/profile")]
+pub async fn update_profile_service(
+    req: HttpRequest,
+    path: web::Path<String>,
+    params: web::Json<ProfileParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if !is_authorized(&req) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    let actor = match actors::actor_lookup(&data, &path.into_inner()) {
+        Err(actors::ResolverError::NotFound) => return HttpResponse::NotFound().finish(),
+        Err(actors::ResolverError::Gone(_)) => return HttpResponse::Gone().finish(),
+        Ok(actor) => actor,
+    };
+
+    if params.fields.len() > actors::MAX_PROFILE_FIELDS {
+        return HttpResponse::BadRequest().body(format!(
+            "Profiles can have at most {} fields",
+            actors::MAX_PROFILE_FIELDS
+        ));
+    }
+    let icon = match &params.avatar {
+        None => None,
+        Some(media_id) => match data.store.get_media(media_id) {
+            Some(document) if document.object_type == OBJECT_TYPE_IMAGE => {
+                Some(ObjectImage::new(&document.media_type, &document.url))
+            }
+            _ => return HttpResponse::BadRequest().body(format!("Unknown image {}", media_id)),
+        },
+    };
+    let summary = profile_summary(&data, &actor, params.summary.as_deref(), params.markdown).await;
+    let fields = params
+        .fields
+        .iter()
+        .map(|field| {
+            ObjectPropertyValue::new(field.name.trim(), &content::escape_html(field.value.trim()))
+        })
+        .collect();
+
+    let profile = actors::ActorProfile {
+        summary,
+        icon,
+        fields,
+    };
+    activities::update_profile(&data, &actor, profile.clone());
+    let actor = actors::LocalActorPerson { profile, ..actor };
+    HttpResponse::Ok().body(serde_json::to_string_pretty(&actor.to_object()).unwrap())
+}
+
+/// Render a bio like the content of a post, sanitized the same way, since
+/// both end up in HTML pages and in other servers' timelines.
+async fn profile_summary(
+    data: &AppState,
+    actor: &actors::LocalActorPerson,
+    summary: Option<&str>,
+    markdown: bool,
+) -> Option<String> {
+    match summary.map(str::trim) {
+        None | Some("") => None,
+        Some(summary) => {
+            let base_url = &actor.hosted_domain().base_url;
+            let html = content::process(data, base_url, summary, markdown)
+                .await
+                .html;
+            Some(sanitize::sanitize_html(&html))
+        }
+    }
+}
+
+#[delete("/api/@{actor_name}")]
+pub async fn delete_actor_service(
+    req: HttpRequest,
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if !is_authorized(&req) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    let actor = match actors::actor_lookup(&data, &path.into_inner()) {
+        Err(actors::ResolverError::NotFound) => return HttpResponse::NotFound().finish(),
+        Err(actors::ResolverError::Gone(_)) => return HttpResponse::Gone().finish(),
+        Ok(actor) => actor,
+    };
+
+    match activities::delete_actor(&data, &actor) {
+        Err(_err) => HttpResponse::NotFound().finish(),
+        Ok(tombstone) => HttpResponse::Ok().body(serde_json::to_string_pretty(&tombstone).unwrap()),
+    }
+}
+
+/// Body accepted by the domain policy endpoint
+#[derive(Deserialize)]
+pub struct DomainPolicyParams {
+    /// `noop`, `silence` or `suspend`
+    severity: Severity,
+    #[serde(default)]
+    reject_media: bool,
+    public_comment: Option<String>,
+}
+
+#[get("/api/domain_policies")]
+pub async fn domain_policies_service(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if !is_authorized(&req) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    HttpResponse::Ok().body(serde_json::to_string_pretty(&data.store.domain_policies()).unwrap())
+}
+
+#[put("/api/domain_policies/{domain}")]
+pub async fn put_domain_policy_service(
+    req: HttpRequest,
+    path: web::Path<String>,
+    params: web::Json<DomainPolicyParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if !is_authorized(&req) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    let domain = match moderation::normalize_domain(&path.into_inner()) {
+        None => return HttpResponse::BadRequest().body("Invalid domain"),
+        Some(domain) => domain,
+    };
+    let params = params.into_inner();
+    let policy = DomainPolicy {
+        domain,
+        severity: params.severity,
+        reject_media: params.reject_media,
+        public_comment: params
+            .public_comment
+            .map(|comment| comment.trim().to_string())
+            .filter(|comment| !comment.is_empty()),
+    };
+    data.store.put_domain_policy(policy.clone());
+    HttpResponse::Ok().body(serde_json::to_string_pretty(&policy).unwrap())
+}
+
+#[delete("/api/domain_policies/{domain}")]
+pub async fn delete_domain_policy_service(
+    req: HttpRequest,
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if !is_authorized(&req) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    let domain = moderation::normalize_domain(&path.into_inner()).unwrap_or_default();
+    if data.store.remove_domain_policy(&domain) {
+        HttpResponse::NoContent().finish()
+    } else {
+        HttpResponse::NotFound().finish()
+    }
+}
+
+/// Every domain policy, as a CSV file in Mastodon's domain block format.
+#[get("/api/domain_policies.csv")]
+pub async fn export_domain_policies_service(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if !is_authorized(&req) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .body(moderation::to_csv(&data.store.domain_policies()))
+}
+
+/// Add or replace policies from a CSV file in Mastodon's domain block
+/// format. Nothing is imported if any row is invalid.
+#[post("/api/domain_policies.csv")]
+pub async fn import_domain_policies_service(
+    req: HttpRequest,
+    body: web::Bytes,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if !is_authorized(&req) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    let csv = match std::str::from_utf8(&body) {
+        Err(_) => return HttpResponse::BadRequest().body("CSV must be UTF-8"),
+        Ok(csv) => csv,
+    };
+    match moderation::from_csv(csv) {
+        Err(err) => HttpResponse::BadRequest().body(format!("{:?}", err)),
+        Ok(policies) => {
+            for policy in &policies {
+                data.store.put_domain_policy(policy.clone());
+            }
+            HttpResponse::Ok().body(serde_json::to_string_pretty(&policies).unwrap())
+        }
+    }
+}
+
+/// Domains we federate with in allowlist mode.
+#[get("/api/domain_allows")]
+pub async fn domain_allows_service(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
+    if !is_authorized(&req) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    HttpResponse::Ok().body(serde_json::to_string_pretty(&data.store.allowed_domains()).unwrap())
+}
+
+#[put("/api/domain_allows/{domain}")]
+pub async fn allow_domain_service(
+    req: HttpRequest,
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if !is_authorized(&req) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    match moderation::normalize_domain(&path.into_inner()) {
+        None => HttpResponse::BadRequest().body("Invalid domain"),
+        Some(domain) if data.store.allow_domain(&domain) => HttpResponse::Created().finish(),
+        Some(_) => HttpResponse::NoContent().finish(),
+    }
+}
+
+#[delete("/api/domain_allows/{domain}")]
+pub async fn disallow_domain_service(
+    req: HttpRequest,
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if !is_authorized(&req) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    let domain = moderation::normalize_domain(&path.into_inner()).unwrap_or_default();
+    if data.store.disallow_domain(&domain) {
+        HttpResponse::NoContent().finish()
+    } else {
+        HttpResponse::NotFound().finish()
+    }
+}
+
+/// Check the request carries the configured admin token as a bearer token.
+/// With no token configured, the API is disabled entirely.
+pub fn is_authorized(req: &HttpRequest) -> bool {
+    let expected = match &config::CONFIG.admin_token {
+        Some(token) => format!("Bearer {}", token),
+        None => return false,
+    };
+    req.headers()
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value == expected)
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delivery::DeliveryQueue;
+    use crate::store::Store;
+    use crate::webfinger::WebfingerClient;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_articles_and_pages_need_a_name() {
+        let params = |value| serde_json::from_value::<PublishNoteParams>(value).unwrap();
+        assert_eq!(
+            kind_and_name(&params(json!({"content": "hi"}))),
+            Ok((ObjectKind::Note, None))
+        );
+        assert_eq!(
+            kind_and_name(&params(
+                json!({"type": "Article", "name": "  Title ", "content": "hi"})
+            )),
+            Ok((ObjectKind::Article, Some("Title".to_string())))
+        );
+        for value in [
+            json!({"type": "Article", "content": "hi"}),
+            json!({"type": "Page", "name": " ", "content": "hi"}),
+            json!({"type": "Question", "name": "Title", "content": "hi"}),
+        ] {
+            assert!(kind_and_name(&params(value)).is_err());
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_profile_summaries_are_sanitized() {
+        let data = AppState {
+            public_key: String::new(),
+            store: Arc::new(Store::new()),
+            delivery: DeliveryQueue::start(),
+            webfinger: Arc::new(WebfingerClient::default()),
+        };
+        let actor = actors::LocalActorPerson::new("a", ACTOR_TYPE_PERSON);
+        for markdown in [true, false] {
+            let summary = profile_summary(
+                &data,
+                &actor,
+                Some("[x](javascript:alert(1)) <script>alert(1)</script> **hi**"),
+                markdown,
+            )
+            .await
+            .unwrap();
+            assert!(!summary.contains("href=\"javascript:"), "{}", summary);
+            assert!(!summary.contains("<script"), "{}", summary);
+        }
+        assert_eq!(profile_summary(&data, &actor, Some("  "), true).await, None);
+    }
+
+    #[test]
+    fn test_poll_expiry_is_bounded() {
+        let params = |expires_in| PollParams {
+            options: vec!["yes".to_string(), "no".to_string()],
+            multiple: false,
+            expires_in,
+        };
+        let poll = poll_from_params(&params(60)).unwrap();
+        assert!(poll.end_time > Utc::now());
+        assert!(poll_from_params(&params(MAX_POLL_DURATION)).is_ok());
+        for expires_in in [0, -1, MAX_POLL_DURATION + 1, i64::MAX] {
+            assert!(poll_from_params(&params(expires_in)).is_err());
+        }
+    }
+}
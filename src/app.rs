@@ -1,15 +1,34 @@
 use std::fs;
+use std::sync::Arc;
+
+use crate::delivery::DeliveryQueue;
+use crate::store::Store;
+use crate::webfinger::WebfingerClient;
 
 #[derive(Clone)]
 pub struct AppState {
     pub public_key: String,
+    pub store: Arc<Store>,
+    pub delivery: DeliveryQueue,
+    pub webfinger: Arc<WebfingerClient>,
 }
 
 impl AppState {
+    /// Build the shared state. Must be called from within the runtime, as it
+    /// starts the delivery worker.
     pub fn new() -> Self {
         AppState {
             public_key: fs::read_to_string("./cert.pem")
                 .expect("Should be able to read public key"),
+            store: Arc::new(Store::new()),
+            delivery: DeliveryQueue::start(),
+            webfinger: Arc::new(WebfingerClient::default()),
         }
     }
 }
+
+impl Default for AppState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
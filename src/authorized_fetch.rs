@@ -0,0 +1,319 @@
+use actix_web::http::header;
+use actix_web::{HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+
+use crate::actors;
+use crate::app::AppState;
+use crate::config;
+use crate::http_signatures::{self, REQUEST_TARGET};
+use crate::moderation;
+use crate::remote_actors;
+
+/// Headers a request's signature must cover for us to trust it.
+const REQUIRED_HEADERS: &[&str] = &[REQUEST_TARGET, "host", "date"];
+
+/// How far the signed Date may be from now, either way.
+const MAX_CLOCK_SKEW_HOURS: i64 = 12;
+
+/// Check that a fetch may be answered. In secure mode the request must be
+/// signed by an actor whose domain we don't reject. Outside it anyone may
+/// fetch anything, but a signature from a rejected domain is still refused.
+pub async fn authorize(data: &AppState, req: &HttpRequest) -> Result<(), AuthorizationError> {
+    let result = signed_by(data, req).await.map(|_| ());
+    if config::CONFIG.authorized_fetch || config::CONFIG.allowlist_mode {
+        return result;
+    }
+    match result {
+        Err(AuthorizationError::Blocked) => result,
+        _ => Ok(()),
+    }
+}
+
+/// The id of the actor that signed a request, once the signature checks out
+/// against its public key.
+pub async fn signed_by(data: &AppState, req: &HttpRequest) -> Result<String, AuthorizationError> {
+    verify(data, req, REQUIRED_HEADERS).await
+}
+
+/// Like `signed_by`, for a request with a body. The Digest must match the
+/// body and be among the signed headers, or the body could be swapped.
+pub async fn signed_post_by(
+    data: &AppState,
+    req: &HttpRequest,
+    body: &[u8],
+) -> Result<String, AuthorizationError> {
+    let digest = req
+        .headers()
+        .get("digest")
+        .and_then(|value| value.to_str().ok())
+        .ok_or(AuthorizationError::Unsigned)?;
+    if !http_signatures::digest_matches(digest, body) {
+        return Err(AuthorizationError::InvalidSignature);
+    }
+    verify(data, req, &[REQUEST_TARGET, "host", "date", "digest"]).await
+}
+
+async fn verify(
+    data: &AppState,
+    req: &HttpRequest,
+    required_headers: &[&str],
+) -> Result<String, AuthorizationError> {
+    let header_value = |name: &str| {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+    let signature = header_value("signature").ok_or(AuthorizationError::Unsigned)?;
+    let params = http_signatures::parse_signature_header(&signature)
+        .map_err(|_| AuthorizationError::InvalidSignature)?;
+    if !required_headers
+        .iter()
+        .all(|required| params.headers.iter().any(|name| name == required))
+    {
+        return Err(AuthorizationError::InvalidSignature);
+    }
+    let date = header_value("date")
+        .and_then(|date| DateTime::parse_from_rfc2822(&date).ok())
+        .ok_or(AuthorizationError::InvalidSignature)?;
+    if (Utc::now() - date.with_timezone(&Utc)).num_hours().abs() > MAX_CLOCK_SKEW_HOURS {
+        return Err(AuthorizationError::InvalidSignature);
+    }
+
+    if moderation::rejects(&data.store, &params.key_id) {
+        return Err(AuthorizationError::Blocked);
+    }
+
+    let path = req
+        .uri()
+        .path_and_query()
+        .map_or(req.path(), |path| path.as_str());
+    let signing_string =
+        http_signatures::signing_string(req.method().as_str(), path, &params.headers, header_value)
+            .map_err(|_| AuthorizationError::InvalidSignature)?;
+    let verify = |public_key_pem: &str| {
+        http_signatures::parse_public_key(public_key_pem)
+            .and_then(|public_key| {
+                http_signatures::verify_signature_with_signing_string_and_public_key(
+                    public_key,
+                    &params.signature,
+                    &signing_string,
+                )
+            })
+            .is_ok()
+    };
+
+    let document = params.key_id.split('#').next().unwrap_or_default();
+    if let Some(actor) = actors::local_actor_by_id(data, document) {
+        if actor.key_id() == params.key_id && verify(&actor.public_key()) {
+            return Ok(actor.actor_id());
+        }
+        return Err(AuthorizationError::InvalidSignature);
+    }
+    let owner = remote_actors::key_owner(data, &params.key_id)
+        .await
+        .map_err(|_| AuthorizationError::InvalidSignature)?;
+    if moderation::rejects(&data.store, &owner.id) {
+        return Err(AuthorizationError::Blocked);
+    }
+    let key_verifies = |actor: &remote_actors::RemoteActor| {
+        actor
+            .public_key
+            .as_ref()
+            .is_some_and(|key| key.id == params.key_id && verify(&key.public_key_pem))
+    };
+    if key_verifies(&owner) {
+        return Ok(owner.id);
+    }
+    // The owner may have rotated its key since we stored it.
+    match remote_actors::fetch_remote_actor(data, &owner.id).await {
+        Ok(owner) if key_verifies(&owner) => Ok(owner.id),
+        _ => Err(AuthorizationError::InvalidSignature),
+    }
+}
+
+/// Why a fetch was refused.
+#[derive(Debug, PartialEq)]
+pub enum AuthorizationError {
+    /// The request carried no signature.
+    Unsigned,
+    /// The signature was malformed, stale, or did not verify.
+    InvalidSignature,
+    /// The signer's domain is rejected.
+    Blocked,
+}
+
+impl AuthorizationError {
+    pub fn response(&self) -> HttpResponse {
+        match self {
+            AuthorizationError::Unsigned | AuthorizationError::InvalidSignature => {
+                HttpResponse::Unauthorized()
+                    .insert_header((header::WWW_AUTHENTICATE, "Signature"))
+                    .finish()
+            }
+            AuthorizationError::Blocked => HttpResponse::Forbidden().finish(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delivery::DeliveryQueue;
+    use crate::http_signatures::tests::{PRIVATE_KEY_PKCS1_PEM, PUBLIC_KEY_PKCS8_PEM};
+    use crate::remote_actors::RemoteActor;
+    use crate::store::Store;
+    use crate::webfinger::WebfingerClient;
+    use actix_web::{test as actix_test, web, App, HttpServer};
+    use chrono::Duration;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn state() -> AppState {
+        AppState {
+            public_key: String::new(),
+            store: Arc::new(Store::new()),
+            delivery: DeliveryQueue::start(),
+            webfinger: Arc::new(WebfingerClient::default()),
+        }
+    }
+
+    fn put_actor(data: &AppState, id: &str, key_id: &str) {
+        let actor = json!({
+            "id": id,
+            "type": "Person",
+            "inbox": format!("{}/inbox", id),
+            "publicKey": {"id": key_id, "owner": id, "publicKeyPem": PUBLIC_KEY_PKCS8_PEM},
+        });
+        data.store
+            .put_remote_actor(RemoteActor::from_value(&actor, Utc::now()).unwrap());
+    }
+
+    /// A GET for `/@a`, signed with `key_id`.
+    fn signed_get(key_id: &str) -> HttpRequest {
+        http_signatures::sign_request(
+            PRIVATE_KEY_PKCS1_PEM,
+            key_id,
+            "GET",
+            "https://example.com/@a",
+            None,
+        )
+        .unwrap()
+        .into_iter()
+        .fold(
+            actix_test::TestRequest::get().uri("/@a"),
+            |request, header| request.insert_header(header),
+        )
+        .to_http_request()
+    }
+
+    #[actix_web::test]
+    async fn test_signed_by_accepts_the_key_owner() {
+        let data = state();
+        let actor = "https://remote.example/users/b";
+        put_actor(&data, actor, &format!("{}#main-key", actor));
+        assert_eq!(
+            signed_by(&data, &signed_get(&format!("{}#main-key", actor))).await,
+            Ok(actor.to_string())
+        );
+        // Not a key the actor lists as its own.
+        assert_eq!(
+            signed_by(&data, &signed_get(&format!("{}#other-key", actor))).await,
+            Err(AuthorizationError::InvalidSignature)
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_rejected_signers_are_refused_outside_secure_mode() {
+        let data = state();
+        let actor = "https://remote.example/users/b";
+        let key_id = format!("{}#main-key", actor);
+        put_actor(&data, actor, &key_id);
+        let unsigned = actix_test::TestRequest::get().uri("/@a").to_http_request();
+        assert_eq!(authorize(&data, &unsigned).await, Ok(()));
+        assert_eq!(authorize(&data, &signed_get(&key_id)).await, Ok(()));
+
+        data.store.put_domain_policy(moderation::DomainPolicy {
+            domain: "remote.example".to_string(),
+            severity: moderation::Severity::Reject,
+            reject_media: false,
+            public_comment: None,
+        });
+        assert_eq!(
+            authorize(&data, &signed_get(&key_id)).await,
+            Err(AuthorizationError::Blocked)
+        );
+        assert_eq!(authorize(&data, &unsigned).await, Ok(()));
+    }
+
+    #[actix_web::test]
+    async fn test_signed_by_resolves_key_documents() {
+        let server = HttpServer::new(|| {
+            App::new().route(
+                "/keys/1",
+                web::get().to(|req: HttpRequest| async move {
+                    let base = format!("http://{}", req.connection_info().host());
+                    HttpResponse::Ok().json(json!({
+                        "id": format!("{}/keys/1", base),
+                        "owner": format!("{}/users/b", base),
+                        "publicKeyPem": PUBLIC_KEY_PKCS8_PEM,
+                    }))
+                }),
+            )
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let base = format!("http://{}", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+        crate::fetch::allow_loopback();
+
+        let data = state();
+        let key_id = format!("{}/keys/1", base);
+        let owner = format!("{}/users/b", base);
+        put_actor(&data, &owner, &key_id);
+        assert_eq!(signed_by(&data, &signed_get(&key_id)).await, Ok(owner));
+    }
+
+    #[actix_web::test]
+    async fn test_signed_by_rejects_bad_signatures() {
+        let data = state();
+        let unsigned = actix_test::TestRequest::get().uri("/@a").to_http_request();
+        assert_eq!(
+            signed_by(&data, &unsigned).await,
+            Err(AuthorizationError::Unsigned)
+        );
+
+        // Only the Date is signed, so the request could be replayed anywhere.
+        let date_only = actix_test::TestRequest::get()
+            .uri("/@a")
+            .insert_header(("date", http_signatures::http_date(Utc::now())))
+            .insert_header((
+                "signature",
+                "keyId=\"https://a.example/@b#main-key\",signature=\"c2ln\"",
+            ))
+            .to_http_request();
+        assert_eq!(
+            signed_by(&data, &date_only).await,
+            Err(AuthorizationError::InvalidSignature)
+        );
+
+        let stale = actix_test::TestRequest::get()
+            .uri("/@a")
+            .insert_header(("host", "example.com"))
+            .insert_header((
+                "date",
+                http_signatures::http_date(Utc::now() - Duration::days(1)),
+            ))
+            .insert_header((
+                "signature",
+                "keyId=\"https://a.example/@b#main-key\",\
+                 headers=\"(request-target) host date\",signature=\"c2ln\"",
+            ))
+            .to_http_request();
+        assert_eq!(
+            signed_by(&data, &stale).await,
+            Err(AuthorizationError::InvalidSignature)
+        );
+    }
+}
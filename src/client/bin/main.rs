@@ -1,30 +1,11 @@
 extern crate dotenv;
 
 use chrono::prelude::*;
-use dotenv::dotenv;
-
-use reqwest;
-
-use rust_activitypub_play::http_signatures;
-use rust_activitypub_play::{config, config::CONFIG};
-use sha2::{Digest, Sha256};
-use std::collections::HashMap;
-
-use activitystreams::{
-    activity::ActorAndObject,
-    actor::{Actor, ApActor, Person},
-    context,
-    iri_string::types::IriString,
-    prelude::*,
-    security,
-    unparsed::UnparsedMutExt,
-};
-use activitystreams_ext::{Ext1, UnparsedExtension};
-
-use serde_json;
-use serde_json::json;
 
-use http::{Request, Response};
+use rust_activitypub_play::config;
+use rust_activitypub_play::webfinger::WebfingerClient;
+
+use serde_json::json;
 
 #[actix_web::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -37,7 +18,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             "id": format!("https://d012-71-36-108-249.ngrok.io/@doctor/{}", date_rfc822),
             "type": "Note",
             "published": date.to_rfc3339(),
-            "attributedTo": "https://d012-71-36-108-249.ngrok.io/@doctor/actor.json",
+            "attributedTo": "https://d012-71-36-108-249.ngrok.io/@doctor",
             "inReplyTo": "https://dev.mastodon.lmorchard.com/@lmorchard/109339034898409760",
             "content": format!("<p>Hello at - {}</p>", date_rfc822),
             "to": "https://www.w3.org/ns/activitystreams#Public"
@@ -48,17 +29,18 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
         "id": format!("https://d012-71-36-108-249.ngrok.io/@doctor/create-{}", date_rfc822),
         "type": "Create",
-        "actor": "https://d012-71-36-108-249.ngrok.io/@doctor/actor.json",
+        "actor": "https://d012-71-36-108-249.ngrok.io/@doctor",
 
         "object": object
     });
 
-    let body = reqwest::get("http://localhost:8080/.well-known/webfinger?resource=ti")
-        .await?
-        .text()
-        .await?;
-
-    println!("body = {:?}", body);
+    let handle = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "@lmorchard@dev.mastodon.lmorchard.com".to_string());
+    match WebfingerClient::default().lookup(&handle).await {
+        Ok(actor_id) => println!("{} is {}", handle, actor_id),
+        Err(err) => println!("Could not resolve {}: {:?}", handle, err),
+    }
 
     // let client = reqwest::Client::builder()
     //     .build()?;
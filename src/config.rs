@@ -6,6 +6,95 @@ pub struct Config {
     pub port: u16,
     pub domain: String,
     pub base_url: String,
+    /// Every domain we host accounts for, the primary `domain` first.
+    pub hosted_domains: Vec<HostedDomain>,
+    pub admin_token: Option<String>,
+    pub local_actors: Vec<String>,
+    pub group_actors: Vec<String>,
+    pub media_dir: String,
+    /// Where the tombstones of deleted actors are kept, reserving their
+    /// names across restarts.
+    pub deleted_actors_file: String,
+    pub max_upload_size: usize,
+    pub media_proxy: bool,
+    /// Advertised in NodeInfo. Accounts are still only created by an admin.
+    pub open_registrations: bool,
+    /// Only serve ActivityPub representations to signed requests.
+    pub authorized_fetch: bool,
+    /// Only federate with domains on the allowlist. Implies
+    /// `authorized_fetch`.
+    pub allowlist_mode: bool,
+    /// Domains rejected from startup, subdomains included.
+    pub blocked_domains: Vec<String>,
+    /// Domains on the allowlist from startup, subdomains included.
+    pub allowed_domains: Vec<String>,
+    /// A Mastodon domain block CSV loaded at startup.
+    pub domain_policies_file: Option<String>,
+}
+
+/// A domain accounts are hosted under, and the URL actors on it are served
+/// from. The two differ in split-domain setups, where handles are
+/// `@user@example.com` but the server runs at `ap.example.com`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostedDomain {
+    pub domain: String,
+    pub base_url: String,
+}
+
+impl HostedDomain {
+    /// The host part of `base_url`.
+    pub fn host(&self) -> &str {
+        let host = self
+            .base_url
+            .split_once("://")
+            .map_or(self.base_url.as_str(), |(_, rest)| rest);
+        host.split('/').next().unwrap_or(host)
+    }
+}
+
+impl Config {
+    /// The hosted domain named either by its account domain or by the host
+    /// its actors are served from.
+    pub fn hosted_domain(&self, domain: &str) -> Option<&HostedDomain> {
+        self.hosted_domains
+            .iter()
+            .find(|hosted| hosted.domain == domain)
+            .or_else(|| {
+                self.hosted_domains
+                    .iter()
+                    .find(|hosted| hosted.host() == domain)
+            })
+    }
+
+    pub fn primary_domain(&self) -> &HostedDomain {
+        &self.hosted_domains[0]
+    }
+}
+
+/// Parse a comma separated list of `domain` or `domain=base_url` entries,
+/// after the primary domain.
+fn parse_hosted_domains(primary: HostedDomain, value: &str, protocol: &str) -> Vec<HostedDomain> {
+    let mut domains = vec![primary];
+    for entry in value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+    {
+        let (domain, base_url) = match entry.split_once('=') {
+            Some((domain, base_url)) => (
+                domain.trim(),
+                base_url.trim().trim_end_matches('/').to_owned(),
+            ),
+            None => (entry, format!("{}://{}", protocol, entry)),
+        };
+        if domains.iter().all(|known| known.domain != domain) {
+            domains.push(HostedDomain {
+                domain: domain.to_owned(),
+                base_url,
+            });
+        }
+    }
+    domains
 }
 
 pub fn init() {
@@ -22,17 +111,119 @@ lazy_static! {
     pub static ref PROTOCOL: String = var("PROTOCOL").unwrap_or_else(|_| "https".to_owned());
     pub static ref DOMAIN: String = var("DOMAIN").unwrap_or_else(|_| {
         if (*PORT == 80 && *PROTOCOL == "http") || (*PORT == 443 && *PROTOCOL == "https") {
-            format!("{}", *HOST)
+            HOST.to_string()
         } else {
             format!("{}:{}", *HOST, *PORT)
         }
     });
     pub static ref BASE_URL: String =
         var("BASE_URL").unwrap_or_else(|_| format!("{}://{}", *PROTOCOL, *DOMAIN));
+    pub static ref HOSTED_DOMAINS: Vec<HostedDomain> = parse_hosted_domains(
+        HostedDomain {
+            domain: DOMAIN.to_string(),
+            base_url: BASE_URL.to_string(),
+        },
+        &var("HOSTED_DOMAINS").unwrap_or_default(),
+        &PROTOCOL,
+    );
+    pub static ref ADMIN_TOKEN: Option<String> = var("ADMIN_TOKEN").ok();
+    pub static ref LOCAL_ACTORS: Vec<String> = var("LOCAL_ACTORS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|name| name.trim().to_owned())
+        .filter(|name| !name.is_empty())
+        .collect();
+    pub static ref GROUP_ACTORS: Vec<String> = var("GROUP_ACTORS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|name| name.trim().to_owned())
+        .filter(|name| !name.is_empty())
+        .collect();
+    pub static ref MEDIA_DIR: String = var("MEDIA_DIR").unwrap_or_else(|_| "./media".to_owned());
+    pub static ref DELETED_ACTORS_FILE: String =
+        var("DELETED_ACTORS_FILE").unwrap_or_else(|_| "./deleted_actors.json".to_owned());
+    pub static ref MAX_UPLOAD_SIZE: usize = var("MAX_UPLOAD_SIZE")
+        .unwrap_or_else(|_| "41943040".to_owned())
+        .parse::<usize>()
+        .unwrap();
+    pub static ref MEDIA_PROXY: bool = var("MEDIA_PROXY")
+        .map(|value| value == "true" || value == "1")
+        .unwrap_or(false);
+    pub static ref OPEN_REGISTRATIONS: bool = var("OPEN_REGISTRATIONS")
+        .map(|value| value == "true" || value == "1")
+        .unwrap_or(false);
+    pub static ref AUTHORIZED_FETCH: bool = var("AUTHORIZED_FETCH")
+        .map(|value| value == "true" || value == "1")
+        .unwrap_or(false);
+    pub static ref ALLOWLIST_MODE: bool = var("ALLOWLIST_MODE")
+        .map(|value| value == "true" || value == "1")
+        .unwrap_or(false);
+    pub static ref BLOCKED_DOMAINS: Vec<String> = var("BLOCKED_DOMAINS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|domain| domain.trim().to_lowercase())
+        .filter(|domain| !domain.is_empty())
+        .collect();
+    pub static ref ALLOWED_DOMAINS: Vec<String> = var("ALLOWED_DOMAINS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|domain| domain.trim().to_lowercase())
+        .filter(|domain| !domain.is_empty())
+        .collect();
+    pub static ref DOMAIN_POLICIES_FILE: Option<String> = var("DOMAIN_POLICIES_FILE")
+        .ok()
+        .filter(|path| !path.is_empty());
     pub static ref CONFIG: Config = Config {
         host: HOST.to_string(),
         port: *PORT,
         domain: DOMAIN.to_string(),
         base_url: BASE_URL.to_string(),
+        hosted_domains: HOSTED_DOMAINS.clone(),
+        admin_token: ADMIN_TOKEN.clone(),
+        local_actors: LOCAL_ACTORS.clone(),
+        group_actors: GROUP_ACTORS.clone(),
+        media_dir: MEDIA_DIR.to_string(),
+        deleted_actors_file: DELETED_ACTORS_FILE.to_string(),
+        max_upload_size: *MAX_UPLOAD_SIZE,
+        media_proxy: *MEDIA_PROXY,
+        open_registrations: *OPEN_REGISTRATIONS,
+        authorized_fetch: *AUTHORIZED_FETCH,
+        allowlist_mode: *ALLOWLIST_MODE,
+        blocked_domains: BLOCKED_DOMAINS.clone(),
+        allowed_domains: ALLOWED_DOMAINS.clone(),
+        domain_policies_file: DOMAIN_POLICIES_FILE.clone(),
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hosted_domains() {
+        let primary = HostedDomain {
+            domain: "example.com".to_owned(),
+            base_url: "https://ap.example.com".to_owned(),
+        };
+        let domains = parse_hosted_domains(
+            primary.clone(),
+            "example.org, example.net=https://social.example.net/ ,example.com",
+            "https",
+        );
+        assert_eq!(
+            domains,
+            vec![
+                primary,
+                HostedDomain {
+                    domain: "example.org".to_owned(),
+                    base_url: "https://example.org".to_owned(),
+                },
+                HostedDomain {
+                    domain: "example.net".to_owned(),
+                    base_url: "https://social.example.net".to_owned(),
+                },
+            ]
+        );
+        assert_eq!(domains[0].host(), "ap.example.com");
+    }
+}
@@ -1,13 +1,44 @@
 pub static CONTEXT_ACTIVITYSTREAMS: &str = "https://www.w3.org/ns/activitystreams";
 pub static CONTEXT_SECURITY: &str = "https://w3id.org/security/v1";
 
+pub static MEDIA_TYPE_ACTIVITY_JSON: &str = "application/activity+json";
+pub static MEDIA_TYPE_LD_JSON: &str =
+    "application/ld+json; profile=\"https://www.w3.org/ns/activitystreams\"";
+pub static MEDIA_TYPE_HTML: &str = "text/html; charset=utf-8";
+
 pub static WEBFINGER_ACTOR_REL: &str = "self";
 pub static WEBFINGER_ACTOR_MEDIA_TYPE: &str = "application/activity+json";
+pub static WEBFINGER_PROFILE_PAGE_REL: &str = "http://webfinger.net/rel/profile-page";
+pub static WEBFINGER_SUBSCRIBE_REL: &str = "http://ostatus.org/schema/1.0/subscribe";
+pub static MEDIA_TYPE_JRD_JSON: &str = "application/jrd+json";
+pub static MEDIA_TYPE_XRD_XML: &str = "application/xrd+xml";
 
 pub static ACTOR_TYPE_PERSON: &str = "Person";
 pub static ACTOR_TYPE_APPLICATION: &str = "Application";
+pub static ACTOR_TYPE_GROUP: &str = "Group";
+pub static ACTOR_TYPE_SERVICE: &str = "Service";
+pub static ACTOR_TYPE_ORGANIZATION: &str = "Organization";
 
 pub static OBJECT_TYPE_NOTE: &str = "Note";
+pub static OBJECT_TYPE_QUESTION: &str = "Question";
+pub static OBJECT_TYPE_ARTICLE: &str = "Article";
+pub static OBJECT_TYPE_PAGE: &str = "Page";
+pub static OBJECT_TYPE_TOMBSTONE: &str = "Tombstone";
+pub static OBJECT_TYPE_COLLECTION: &str = "Collection";
+pub static OBJECT_TYPE_ORDERED_COLLECTION: &str = "OrderedCollection";
+pub static OBJECT_TYPE_IMAGE: &str = "Image";
+pub static OBJECT_TYPE_DOCUMENT: &str = "Document";
+pub static OBJECT_TYPE_PROPERTY_VALUE: &str = "PropertyValue";
+
+pub static TAG_TYPE_MENTION: &str = "Mention";
+pub static TAG_TYPE_HASHTAG: &str = "Hashtag";
+pub static TAG_TYPE_EMOJI: &str = "Emoji";
+
 pub static ACTIVITY_TYPE_CREATE: &str = "Create";
+pub static ACTIVITY_TYPE_DELETE: &str = "Delete";
+pub static ACTIVITY_TYPE_UPDATE: &str = "Update";
+pub static ACTIVITY_TYPE_LIKE: &str = "Like";
+pub static ACTIVITY_TYPE_UNDO: &str = "Undo";
+pub static ACTIVITY_TYPE_ANNOUNCE: &str = "Announce";
 
 pub static TO_PUBLIC: &str = "https://www.w3.org/ns/activitystreams#Public";
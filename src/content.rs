@@ -0,0 +1,312 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use log::warn;
+use pulldown_cmark::{html, Event, Parser, Tag};
+use regex::{Captures, Regex};
+
+use crate::actors::actor_lookup;
+use crate::app::AppState;
+use crate::config;
+use crate::constants::*;
+use crate::objects::{ObjectImage, ObjectTag};
+
+lazy_static! {
+    /// `@user@domain` mentions and `#hashtags`, each preceded by something
+    /// that cannot be part of a word, URL or another token.
+    static ref TOKEN: Regex = Regex::new(
+        r"(?P<pre>^|[^\w@/#&:])(?:@(?P<user>[A-Za-z0-9_]+(?:[.-][A-Za-z0-9_]+)*)@(?P<domain>[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*(?::\d+)?)|#(?P<tag>\w*[A-Za-z_]\w*))"
+    )
+    .unwrap();
+    static ref EMOJI: Regex = Regex::new(r":(?P<shortcode>[A-Za-z0-9_]{2,}):").unwrap();
+}
+
+/// Directory custom emoji images are served from, as `{shortcode}.png`.
+pub const EMOJI_DIR: &str = "./static/emoji";
+
+/// Note content rendered to HTML, with the tags and recipients it implies.
+#[derive(Debug, Default, PartialEq)]
+pub struct ProcessedContent {
+    pub html: String,
+    pub tags: Vec<ObjectTag>,
+    /// Actor ids of everyone mentioned, to be added to `cc`.
+    pub mentions: Vec<String>,
+}
+
+/// Render authored text to HTML, linking mentions and hashtags. Mentions are
+/// resolved through WebFinger; ones that cannot be resolved stay plain text.
+/// Hashtags and emoji link to `base_url`, that of the author's domain.
+pub async fn process(
+    data: &AppState,
+    base_url: &str,
+    source: &str,
+    markdown: bool,
+) -> ProcessedContent {
+    let mut mentions = BTreeMap::new();
+    for captures in TOKEN.captures_iter(source) {
+        if let (Some(user), Some(domain)) = (captures.name("user"), captures.name("domain")) {
+            let handle = format!("{}@{}", user.as_str(), domain.as_str());
+            if mentions.contains_key(&handle) {
+                continue;
+            }
+            if let Some(actor_id) = resolve_mention(data, user.as_str(), domain.as_str()).await {
+                mentions.insert(handle, actor_id);
+            }
+        }
+    }
+    render(base_url, source, markdown, &mentions)
+}
+
+async fn resolve_mention(data: &AppState, user: &str, domain: &str) -> Option<String> {
+    if let Some(hosted) = config::CONFIG.hosted_domain(domain) {
+        return actor_lookup(data, user)
+            .ok()
+            .filter(|actor| actor.domain == hosted.domain)
+            .map(|actor| actor.actor_id());
+    }
+    match data.webfinger.lookup(&format!("{}@{}", user, domain)).await {
+        Ok(actor_id) => Some(actor_id),
+        Err(err) => {
+            warn!("Could not resolve mention @{}@{}: {:?}", user, domain, err);
+            None
+        }
+    }
+}
+
+/// Render with mentions already resolved, mapping `user@domain` to actor id.
+pub fn render(
+    base_url: &str,
+    source: &str,
+    markdown: bool,
+    mentions: &BTreeMap<String, String>,
+) -> ProcessedContent {
+    let mut tags = Vec::new();
+    let html = if markdown {
+        render_markdown(base_url, source, mentions, &mut tags)
+    } else {
+        render_plain(base_url, source, mentions, &mut tags)
+    };
+
+    for captures in EMOJI.captures_iter(source) {
+        let shortcode = &captures["shortcode"];
+        let name = format!(":{}:", shortcode);
+        let exists = Path::new(EMOJI_DIR)
+            .join(format!("{}.png", shortcode))
+            .is_file();
+        if exists && !tags.iter().any(|tag: &ObjectTag| tag.name == name) {
+            tags.push(ObjectTag::emoji(
+                &name,
+                ObjectImage::new(
+                    "image/png",
+                    &format!("{}/emoji/{}.png", base_url, shortcode),
+                ),
+            ));
+        }
+    }
+
+    let mentions = tags
+        .iter()
+        .filter(|tag| tag.tag_type == TAG_TYPE_MENTION)
+        .filter_map(|tag| tag.href.clone())
+        .collect();
+    ProcessedContent {
+        html,
+        tags,
+        mentions,
+    }
+}
+
+fn render_plain(
+    base_url: &str,
+    source: &str,
+    mentions: &BTreeMap<String, String>,
+    tags: &mut Vec<ObjectTag>,
+) -> String {
+    source
+        .replace("\r\n", "\n")
+        .split("\n\n")
+        .map(str::trim)
+        .filter(|paragraph| !paragraph.is_empty())
+        .map(|paragraph| {
+            let lines: Vec<String> = paragraph
+                .lines()
+                .map(|line| linkify(base_url, line, mentions, tags))
+                .collect();
+            format!("<p>{}</p>", lines.join("<br>"))
+        })
+        .collect()
+}
+
+fn render_markdown(
+    base_url: &str,
+    source: &str,
+    mentions: &BTreeMap<String, String>,
+    tags: &mut Vec<ObjectTag>,
+) -> String {
+    // Only plain text gets linked: not code, and not text already in a link.
+    let mut verbatim = 0;
+    let events: Vec<Event> = Parser::new(source)
+        .map(|event| match event {
+            Event::Start(tag @ (Tag::CodeBlock(_) | Tag::Link(..) | Tag::Image(..))) => {
+                verbatim += 1;
+                Event::Start(tag)
+            }
+            Event::End(tag @ (Tag::CodeBlock(_) | Tag::Link(..) | Tag::Image(..))) => {
+                verbatim -= 1;
+                Event::End(tag)
+            }
+            Event::Text(text) if verbatim == 0 => {
+                Event::Html(linkify(base_url, &text, mentions, tags).into())
+            }
+            // Raw HTML in the source is shown as text rather than trusted.
+            Event::Html(text) => Event::Text(text),
+            event => event,
+        })
+        .collect();
+
+    let mut output = String::new();
+    html::push_html(&mut output, events.into_iter());
+    output.trim_end().to_string()
+}
+
+/// Escape `text` for HTML, turning mentions and hashtags into links and
+/// collecting a tag for each.
+fn linkify(
+    base_url: &str,
+    text: &str,
+    mentions: &BTreeMap<String, String>,
+    tags: &mut Vec<ObjectTag>,
+) -> String {
+    let mut output = String::new();
+    let mut last = 0;
+    for captures in TOKEN.captures_iter(text) {
+        let token = captures.get(0).unwrap();
+        let pre = captures.name("pre").unwrap();
+        output.push_str(&escape_html(&text[last..pre.end()]));
+        output.push_str(&link_token(base_url, &captures, mentions, tags));
+        last = token.end();
+    }
+    output.push_str(&escape_html(&text[last..]));
+    output
+}
+
+fn link_token(
+    base_url: &str,
+    captures: &Captures,
+    mentions: &BTreeMap<String, String>,
+    tags: &mut Vec<ObjectTag>,
+) -> String {
+    let original = &captures[0][captures["pre"].len()..];
+
+    if let Some(tag) = captures.name("tag") {
+        let tag = tag.as_str();
+        let href = format!("{}/tags/{}", base_url, tag.to_lowercase());
+        let name = format!("#{}", tag.to_lowercase());
+        if !tags.iter().any(|existing| existing.name == name) {
+            tags.push(ObjectTag::hashtag(&name, &href));
+        }
+        return format!(
+            "<a href=\"{}\" class=\"mention hashtag\" rel=\"tag\">#<span>{}</span></a>",
+            escape_html(&href),
+            escape_html(tag)
+        );
+    }
+
+    let user = &captures["user"];
+    let handle = format!("{}@{}", user, &captures["domain"]);
+    match mentions.get(&handle) {
+        None => escape_html(original),
+        Some(actor_id) => {
+            let name = format!("@{}", handle);
+            if !tags.iter().any(|existing| existing.name == name) {
+                tags.push(ObjectTag::mention(&name, actor_id));
+            }
+            format!(
+                "<span class=\"h-card\"><a href=\"{}\" class=\"u-url mention\">@<span>{}</span></a></span>",
+                escape_html(actor_id),
+                escape_html(user)
+            )
+        }
+    }
+}
+
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mentions() -> BTreeMap<String, String> {
+        BTreeMap::from([(
+            "alice@remote.example".to_string(),
+            "https://remote.example/users/alice".to_string(),
+        )])
+    }
+
+    #[test]
+    fn test_plain_text_mentions_and_hashtags() {
+        let result = render(
+            &config::CONFIG.base_url,
+            "hi @alice@remote.example and @bob@nowhere.example #Rust\n<b>ok</b>",
+            false,
+            &mentions(),
+        );
+        assert_eq!(
+            result.html,
+            "<p>hi <span class=\"h-card\"><a href=\"https://remote.example/users/alice\" class=\"u-url mention\">@<span>alice</span></a></span> and @bob@nowhere.example <a href=\""
+                .to_string()
+                + &config::CONFIG.base_url
+                + "/tags/rust\" class=\"mention hashtag\" rel=\"tag\">#<span>Rust</span></a><br>&lt;b&gt;ok&lt;/b&gt;</p>"
+        );
+        assert_eq!(result.mentions, vec!["https://remote.example/users/alice"]);
+        let names: Vec<&str> = result.tags.iter().map(|tag| tag.name.as_str()).collect();
+        assert_eq!(names, vec!["@alice@remote.example", "#rust"]);
+    }
+
+    #[test]
+    fn test_markdown_skips_code_and_links() {
+        let result = render(
+            &config::CONFIG.base_url,
+            "*hi* @alice@remote.example `#notatag` [#nope](https://example.com/#x)",
+            true,
+            &mentions(),
+        );
+        assert!(result.html.starts_with("<p><em>hi</em> <span class=\"h-card\">"));
+        assert!(result.html.contains("<code>#notatag</code>"));
+        assert!(result.html.contains("<a href=\"https://example.com/#x\">#nope</a>"));
+        assert_eq!(result.tags.len(), 1);
+    }
+
+    #[test]
+    fn test_tokens_inside_words_are_ignored() {
+        let result = render(
+            &config::CONFIG.base_url,
+            "mail me@alice@remote.example or go to a#b",
+            false,
+            &mentions(),
+        );
+        assert_eq!(
+            result.html,
+            "<p>mail me@alice@remote.example or go to a#b</p>"
+        );
+        assert!(result.tags.is_empty());
+    }
+
+    #[test]
+    fn test_links_use_the_given_base_url() {
+        let result = render("https://other.example", "#Rust", false, &mentions());
+        assert!(result
+            .html
+            .contains("<a href=\"https://other.example/tags/rust\""));
+        assert_eq!(
+            result.tags[0].href.as_deref(),
+            Some("https://other.example/tags/rust")
+        );
+    }
+}
@@ -0,0 +1,149 @@
+use std::time::Duration;
+
+use log::{info, warn};
+use serde::Serialize;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+use crate::actors::LocalActorPerson;
+use crate::fetch;
+use crate::http_signatures;
+use crate::jsonld;
+use crate::moderation;
+use crate::store::Store;
+
+/// How many times a delivery is attempted before it is dropped.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// A single activity waiting to be POSTed to a remote inbox.
+#[derive(Debug, Clone)]
+pub struct DeliveryJob {
+    pub inbox: String,
+    pub key_id: String,
+    pub private_key: String,
+    pub body: String,
+}
+
+/// Queue of outgoing activities, drained by a background worker that signs
+/// and sends each one.
+#[derive(Clone)]
+pub struct DeliveryQueue {
+    sender: UnboundedSender<DeliveryJob>,
+}
+
+impl DeliveryQueue {
+    /// Create the queue and spawn its worker on the current runtime.
+    pub fn start() -> Self {
+        let (sender, receiver) = unbounded_channel();
+        actix_web::rt::spawn(deliver_all(receiver));
+        DeliveryQueue { sender }
+    }
+
+    /// Queue `activity` for delivery to each of `inboxes`, signed by `actor`.
+    /// Inboxes on servers we reject, or don't allow in allowlist mode, are
+    /// skipped.
+    pub fn enqueue<T: Serialize>(
+        &self,
+        store: &Store,
+        actor: &LocalActorPerson,
+        inboxes: &[String],
+        activity: &T,
+    ) {
+        let body = match serde_json::to_string(&jsonld::with_context(activity)) {
+            Ok(body) => body,
+            Err(err) => {
+                warn!("Could not serialize activity for delivery: {}", err);
+                return;
+            }
+        };
+        for inbox in deliverable(store, inboxes) {
+            let job = DeliveryJob {
+                inbox: inbox.clone(),
+                key_id: actor.key_id(),
+                private_key: actor.private_key(),
+                body: body.clone(),
+            };
+            if self.sender.send(job).is_err() {
+                warn!("Delivery worker has stopped, dropping delivery to {}", inbox);
+            }
+        }
+    }
+}
+
+async fn deliver_all(mut receiver: UnboundedReceiver<DeliveryJob>) {
+    while let Some(job) = receiver.recv().await {
+        let client = fetch::CLIENT.clone();
+        actix_web::rt::spawn(async move {
+            if !fetch::is_public_url(&job.inbox).await {
+                warn!("Not delivering to {}: not a public address", job.inbox);
+                return;
+            }
+            for attempt in 1..=MAX_ATTEMPTS {
+                match deliver(&client, &job).await {
+                    Ok(()) => {
+                        info!("Delivered activity to {}", job.inbox);
+                        return;
+                    }
+                    Err(err) => {
+                        warn!("Delivery to {} failed (attempt {}): {}", job.inbox, attempt, err);
+                        actix_web::rt::time::sleep(Duration::from_secs(2u64.pow(attempt))).await;
+                    }
+                }
+            }
+            warn!("Giving up on delivery to {}", job.inbox);
+        });
+    }
+}
+
+async fn deliver(
+    client: &reqwest::Client,
+    job: &DeliveryJob,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let headers = http_signatures::sign_request(
+        &job.private_key,
+        &job.key_id,
+        "POST",
+        &job.inbox,
+        Some(job.body.as_bytes()),
+    )?;
+
+    let mut request = client
+        .post(&job.inbox)
+        .header("content-type", "application/activity+json")
+        .body(job.body.clone());
+    for (name, value) in headers {
+        request = request.header(name, value);
+    }
+    request.send().await?.error_for_status()?;
+    Ok(())
+}
+
+/// The inboxes of `inboxes` on servers we federate with.
+fn deliverable<'a>(store: &Store, inboxes: &'a [String]) -> Vec<&'a String> {
+    inboxes
+        .iter()
+        .filter(|inbox| !moderation::rejects(store, inbox))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::moderation::{DomainPolicy, Severity};
+
+    #[test]
+    fn test_rejected_inboxes_are_not_delivered_to() {
+        let store = Store::new();
+        store.put_domain_policy(DomainPolicy {
+            domain: "rejected.example".to_string(),
+            severity: Severity::Reject,
+            reject_media: false,
+            public_comment: None,
+        });
+        let inboxes = [
+            "https://rejected.example/inbox".to_string(),
+            "https://social.rejected.example/inbox".to_string(),
+            "https://remote.example/inbox".to_string(),
+        ];
+        assert_eq!(deliverable(&store, &inboxes), [&inboxes[2]]);
+    }
+}
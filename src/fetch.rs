@@ -0,0 +1,397 @@
+use std::collections::BTreeSet;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::time::Duration;
+
+use actix_web::web;
+use log::warn;
+use reqwest::{header, redirect, Response};
+use serde_json::Value;
+use url::{Host, Url};
+
+use crate::actors::LocalActorPerson;
+use crate::http_signatures;
+use crate::inbox::{property_ids, same_origin};
+use crate::jsonld;
+use crate::store::Store;
+
+/// Media types we accept for ActivityPub objects, in order of preference.
+const ACCEPT: &str = concat!(
+    "application/activity+json, ",
+    "application/ld+json; profile=\"https://www.w3.org/ns/activitystreams\""
+);
+
+/// Redirects followed before giving up on a fetch.
+const MAX_REDIRECTS: usize = 3;
+
+/// Largest response body read, in bytes. Actors and notes are a few
+/// kilobytes; anything much bigger is not an object we want.
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+/// How long a fetch may take, from connecting to reading the last byte.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How far up a reply chain we go before giving up.
+const MAX_REPLY_DEPTH: usize = 20;
+
+lazy_static! {
+    /// Client for every request to another server. Redirects to private
+    /// addresses are refused, so a remote server can't point us at our own
+    /// network.
+    pub static ref CLIENT: reqwest::Client = reqwest::Client::builder()
+        .redirect(redirect::Policy::custom(|attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                attempt.error("too many redirects")
+            } else if is_private_host(attempt.url()) {
+                attempt.error("redirect to a private address")
+            } else {
+                attempt.follow()
+            }
+        }))
+        .timeout(TIMEOUT)
+        .build()
+        .expect("Should be able to build the fetch client");
+}
+
+/// Dereference an ActivityPub object by id, normalised to the terms we
+/// match against. The object must be served from the server its id names.
+pub async fn fetch_object(url: &str) -> Result<Value, FetchError> {
+    get(url, None).await
+}
+
+/// Like `fetch_object`, with the request signed by a local actor, for servers
+/// that only serve signed requests.
+pub async fn fetch_object_signed(
+    url: &str,
+    signer: &LocalActorPerson,
+) -> Result<Value, FetchError> {
+    get(url, Some(signer)).await
+}
+
+async fn get(url: &str, signer: Option<&LocalActorPerson>) -> Result<Value, FetchError> {
+    if !is_public_url(url).await && !loopback_allowed(url) {
+        return Err(FetchError::PrivateAddress);
+    }
+    let mut request = CLIENT.get(url).header(header::ACCEPT, ACCEPT);
+    if let Some(signer) = signer {
+        let headers = http_signatures::sign_request(
+            &signer.private_key(),
+            &signer.key_id(),
+            "GET",
+            url,
+            None,
+        )
+        .map_err(|_| FetchError::RequestFailed)?;
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+    }
+    let response = request
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|_| FetchError::RequestFailed)?;
+    // Where the object actually came from, after any redirects.
+    let served_from = response.url().clone();
+    let body = read_body(response).await?;
+    parse_object(&body, &served_from)
+}
+
+/// Parse a fetched object, which must name the origin it was served from as
+/// its own.
+fn parse_object(body: &[u8], served_from: &Url) -> Result<Value, FetchError> {
+    let mut object =
+        serde_json::from_slice::<Value>(body).map_err(|_| FetchError::InvalidResponse)?;
+    match object["id"].as_str() {
+        Some(id) if same_origin(id, served_from.as_str()) => (),
+        Some(_) => return Err(FetchError::WrongOrigin),
+        None => return Err(FetchError::InvalidResponse),
+    }
+    jsonld::normalize(&mut object);
+    Ok(object)
+}
+
+/// Whether a URL names a host on a loopback, private or link-local network,
+/// without resolving it.
+fn is_private_host(url: &Url) -> bool {
+    match url.host() {
+        Some(Host::Ipv4(ip)) => is_private_ip(IpAddr::V4(ip)),
+        Some(Host::Ipv6(ip)) => is_private_ip(IpAddr::V6(ip)),
+        Some(Host::Domain(domain)) => {
+            let domain = domain.trim_end_matches('.').to_lowercase();
+            domain == "localhost" || domain.ends_with(".localhost")
+        }
+        None => true,
+    }
+}
+
+fn is_private_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                // Shared address space, 100.64.0.0/10
+                || (ip.octets()[0] == 100 && ip.octets()[1] & 0xc0 == 64)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_private_ip(IpAddr::V4(ip)),
+            None => {
+                ip.is_loopback()
+                    || ip.is_unspecified()
+                    // Unique local, fc00::/7, and link-local, fe80::/10
+                    || ip.segments()[0] & 0xfe00 == 0xfc00
+                    || ip.segments()[0] & 0xffc0 == 0xfe80
+            }
+        },
+    }
+}
+
+/// Whether a URL is safe to fetch on behalf of a remote server: http(s), on a
+/// host that doesn't resolve to a private address.
+pub async fn is_public_url(url: &str) -> bool {
+    let url = match Url::parse(url) {
+        Ok(url) if url.scheme() == "https" || url.scheme() == "http" => url,
+        _ => return false,
+    };
+    if is_private_host(&url) {
+        return false;
+    }
+    let (host, port) = match (url.domain(), url.port_or_known_default()) {
+        (Some(host), Some(port)) => (host.to_string(), port),
+        // An IP address, already checked above.
+        _ => return true,
+    };
+    match web::block(move || (host, port).to_socket_addrs()).await {
+        Ok(Ok(mut addresses)) => addresses.all(|address| !is_private_ip(address.ip())),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    static ALLOW_LOOPBACK: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+/// Let fetches on this thread reach 127.0.0.1, where tests serve objects.
+#[cfg(test)]
+pub(crate) fn allow_loopback() {
+    ALLOW_LOOPBACK.with(|allow| allow.set(true));
+}
+
+#[cfg(test)]
+fn loopback_allowed(url: &str) -> bool {
+    ALLOW_LOOPBACK.with(|allow| allow.get())
+        && Url::parse(url).is_ok_and(|url| url.host_str() == Some("127.0.0.1"))
+}
+
+#[cfg(not(test))]
+fn loopback_allowed(_url: &str) -> bool {
+    false
+}
+
+/// Read a response body, refusing it as soon as it grows past
+/// `MAX_BODY_SIZE` rather than trusting `Content-Length`.
+async fn read_body(mut response: Response) -> Result<Vec<u8>, FetchError> {
+    if response
+        .content_length()
+        .is_some_and(|length| length > MAX_BODY_SIZE as u64)
+    {
+        return Err(FetchError::TooLarge);
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|_| FetchError::RequestFailed)?
+    {
+        if body.len() + chunk.len() > MAX_BODY_SIZE {
+            return Err(FetchError::TooLarge);
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+/// Fetch the objects `object` is a reply to, nearest first. The walk stops
+/// at an object the store already has, at `MAX_REPLY_DEPTH`, or at the first
+/// object that can't be fetched; a chain that loops back on itself is an
+/// error.
+pub async fn fetch_reply_chain(
+    store: &Store,
+    object: &Value,
+) -> Result<Vec<(String, Value)>, FetchError> {
+    let mut seen: BTreeSet<String> = object["id"]
+        .as_str()
+        .map(str::to_string)
+        .into_iter()
+        .collect();
+    let mut chain = Vec::new();
+    let mut next = property_ids(&object["inReplyTo"]).pop();
+    while let Some(parent_id) = next {
+        if chain.len() == MAX_REPLY_DEPTH
+            || store.get_object(&parent_id).is_some()
+            || store.get_remote_object(&parent_id).is_some()
+        {
+            break;
+        }
+        if !seen.insert(parent_id.clone()) {
+            return Err(FetchError::Cycle);
+        }
+        let parent = match fetch_object(&parent_id).await {
+            Ok(parent) if parent["id"].as_str() == Some(parent_id.as_str()) => parent,
+            Ok(_) => {
+                warn!("Fetched object does not match requested id {}", parent_id);
+                break;
+            }
+            Err(err) => {
+                warn!("Could not fetch {} in reply chain: {:?}", parent_id, err);
+                break;
+            }
+        };
+        next = property_ids(&parent["inReplyTo"]).pop();
+        chain.push((parent_id, parent));
+    }
+    Ok(chain)
+}
+
+/// An error that occured while fetching a remote object.
+#[derive(Debug, PartialEq)]
+pub enum FetchError {
+    /// The remote server could not be reached, returned an error, redirected
+    /// too often or took too long.
+    RequestFailed,
+    /// The URL is not http(s), or names a loopback, private or link-local
+    /// address.
+    PrivateAddress,
+    /// The response was bigger than we are willing to read.
+    TooLarge,
+    /// The response was not the JSON object we expected.
+    InvalidResponse,
+    /// The object's id is on a different server from the one we asked.
+    WrongOrigin,
+    /// A chain of replies led back to an object already in it.
+    Cycle,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{web, App, HttpResponse, HttpServer};
+    use serde_json::json;
+
+    /// Serve a few fixed objects on a local port, returning its base URL.
+    fn start_server() -> String {
+        let server = HttpServer::new(|| {
+            App::new().route(
+                "/{name}",
+                web::get().to(
+                    |req: actix_web::HttpRequest, path: web::Path<String>| async move {
+                        let base = format!("http://{}", req.connection_info().host());
+                        let note = |id: &str, parent: &str| {
+                            json!({
+                                "id": format!("{}/{}", base, id),
+                                "type": "Note",
+                                "inReplyTo": format!("{}/{}", base, parent),
+                            })
+                        };
+                        match path.as_str() {
+                            "a" => HttpResponse::Ok().json(note("a", "b")),
+                            "b" => HttpResponse::Ok().json(note("b", "a")),
+                            "c" => HttpResponse::Ok().json(note("c", "missing")),
+                            "d" => HttpResponse::Ok().json(note("d", "c")),
+                            "foreign" => HttpResponse::Ok()
+                                .json(json!({"id": "https://elsewhere.example/1"})),
+                            "huge" => HttpResponse::Ok().body(vec![b' '; MAX_BODY_SIZE + 1]),
+                            "loop" => HttpResponse::Found()
+                                .insert_header(("location", "/loop"))
+                                .finish(),
+                            _ => HttpResponse::NotFound().finish(),
+                        }
+                    },
+                ),
+            )
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let base = format!("http://{}", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+        base
+    }
+
+    #[actix_web::test]
+    async fn test_fetch_object_limits() {
+        let base = start_server();
+        assert_eq!(
+            fetch_object(&format!("{}/c", base)).await,
+            Err(FetchError::PrivateAddress)
+        );
+        allow_loopback();
+        let object = fetch_object(&format!("{}/c", base)).await.unwrap();
+        assert_eq!(object["type"], "Note");
+        assert_eq!(
+            fetch_object(&format!("{}/foreign", base)).await,
+            Err(FetchError::WrongOrigin)
+        );
+        assert_eq!(
+            fetch_object(&format!("{}/huge", base)).await,
+            Err(FetchError::TooLarge)
+        );
+        assert_eq!(
+            fetch_object(&format!("{}/loop", base)).await,
+            Err(FetchError::RequestFailed)
+        );
+    }
+
+    #[test]
+    fn test_objects_must_come_from_the_server_they_name() {
+        let body = br#"{"id": "https://a.example/1", "type": "Note"}"#;
+        let served_from = Url::parse("https://a.example/redirected").unwrap();
+        assert!(parse_object(body, &served_from).is_ok());
+        // Asked a.example, but were redirected to b.example, which claims an
+        // object on a.example.
+        let served_from = Url::parse("https://b.example/1").unwrap();
+        assert_eq!(
+            parse_object(body, &served_from),
+            Err(FetchError::WrongOrigin)
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_private_addresses_are_not_public() {
+        for url in [
+            "http://127.0.0.1/a",
+            "http://10.1.2.3/a",
+            "http://169.254.169.254/latest",
+            "http://[::1]/a",
+            "http://[::ffff:192.168.0.1]/a",
+            "http://[fd00::1]/a",
+            "http://localhost:8080/a",
+            "http://x.localhost/a",
+            "file:///etc/passwd",
+        ] {
+            assert!(!is_public_url(url).await, "{}", url);
+        }
+        assert!(is_public_url("https://93.184.216.34/a").await);
+        assert!(is_public_url("https://[2606:4700::1]/a").await);
+    }
+
+    #[actix_web::test]
+    async fn test_fetch_reply_chain() {
+        let base = start_server();
+        allow_loopback();
+        let store = Store::new();
+        let reply = |parent: &str| json!({"id": "https://x.example/1", "inReplyTo": format!("{}/{}", base, parent)});
+
+        let chain = fetch_reply_chain(&store, &reply("d")).await.unwrap();
+        let ids: Vec<_> = chain.iter().map(|(id, _)| id.clone()).collect();
+        assert_eq!(ids, [format!("{}/d", base), format!("{}/c", base)]);
+
+        assert_eq!(
+            fetch_reply_chain(&store, &reply("a")).await,
+            Err(FetchError::Cycle)
+        );
+    }
+}
@@ -0,0 +1,516 @@
+use serde_json::Value;
+
+use crate::actors::LocalActorPerson;
+use crate::config;
+use crate::constants::*;
+use crate::content::escape_html;
+use crate::objects::{ObjectCollection, ObjectDocument, ObjectNote};
+use crate::remote_actors::is_actor_type;
+use crate::sanitize::to_plain_text;
+
+/// Posts shown on each page of a profile.
+pub const PAGE_SIZE: usize = 20;
+
+/// Longest description given in link preview meta tags, in characters.
+const DESCRIPTION_LENGTH: usize = 200;
+
+/// The profile page of a local actor, served at its id to browsers. `posts`
+/// is one page of its public posts, newest first.
+pub fn actor_page(
+    actor: &LocalActorPerson,
+    posts: &[ObjectNote],
+    page: usize,
+    has_more: bool,
+) -> String {
+    let profile = &actor.profile;
+    let handle = format!("@{}", actor.handle());
+    let title = format!("{} ({})", actor.name, handle);
+    let description = profile.summary.as_deref().map(excerpt).unwrap_or_default();
+    let image = profile.icon.as_ref().map(|icon| icon.url.as_str());
+
+    let mut body = String::from("<header>\n");
+    if let Some(image) = image {
+        body.push_str(&format!(
+            "<img class=\"avatar\" src=\"{}\" alt=\"\">\n",
+            escape_html(image)
+        ));
+    }
+    body.push_str(&format!(
+        "<h1>{}</h1>\n<p>{}</p>\n",
+        escape_html(&actor.name),
+        escape_html(&handle)
+    ));
+    // The bio and field values were sanitized when the profile was saved.
+    if let Some(summary) = &profile.summary {
+        body.push_str(&format!("<div class=\"summary\">{}</div>\n", summary));
+    }
+    if !profile.fields.is_empty() {
+        body.push_str("<dl>\n");
+        for field in &profile.fields {
+            body.push_str(&format!(
+                "<dt>{}</dt><dd>{}</dd>\n",
+                escape_html(&field.name),
+                field.value
+            ));
+        }
+        body.push_str("</dl>\n");
+    }
+    body.push_str("</header>\n");
+
+    for post in posts {
+        body.push_str(&article(actor, post, true));
+    }
+
+    let base = actor.actor_html_url();
+    let newer = match page {
+        0 => None,
+        1 => Some(base.clone()),
+        page => Some(format!("{}?page={}", base, page - 1)),
+    };
+    let older = has_more.then(|| format!("{}?page={}", base, page + 1));
+    if newer.is_some() || older.is_some() {
+        body.push_str("<nav>\n");
+        if let Some(newer) = newer {
+            body.push_str(&format!(
+                "<a rel=\"prev\" href=\"{}\">Newer</a>\n",
+                escape_html(&newer)
+            ));
+        }
+        if let Some(older) = older {
+            body.push_str(&format!(
+                "<a rel=\"next\" href=\"{}\">Older</a>\n",
+                escape_html(&older)
+            ));
+        }
+        body.push_str("</nav>\n");
+    }
+
+    let meta = Meta {
+        site_name: &actor.hosted_domain().domain,
+        og_type: "profile",
+        title: &title,
+        description: &description,
+        url: &base,
+        image,
+        large_image: false,
+        published: None,
+    };
+    page_html(&title, &meta, &actor.actor_id(), &body)
+}
+
+/// The page for a local note, article or page, served at its id to browsers.
+/// `parent` is the object it replies to, if we know it.
+pub fn object_page(
+    actor: &LocalActorPerson,
+    object: &ObjectNote,
+    parent: Option<&Value>,
+) -> String {
+    let title = match &object.name {
+        Some(name) => name.clone(),
+        None => format!("{} (@{})", actor.name, actor.handle()),
+    };
+    // Only the content warning is given away for sensitive posts.
+    let description = match (&object.summary, object.sensitive) {
+        (Some(summary), Some(true)) => excerpt(summary),
+        (Some(summary), _) if object.name.is_some() => excerpt(summary),
+        _ => excerpt(&object.content),
+    };
+    let image = match object.sensitive {
+        Some(true) => None,
+        _ => object
+            .attachment
+            .iter()
+            .find(|attachment| attachment.object_type == OBJECT_TYPE_IMAGE),
+    };
+
+    let mut body = String::new();
+    if let Some(in_reply_to) = &object.in_reply_to {
+        body.push_str(&reply_context(in_reply_to, parent));
+    }
+    body.push_str(&article(actor, object, false));
+
+    let published = object.published.to_rfc3339();
+    let meta = Meta {
+        site_name: &actor.hosted_domain().domain,
+        og_type: "article",
+        title: &title,
+        description: &description,
+        url: object.url.as_deref().unwrap_or(&object.id),
+        image: image
+            .map(|image| image.url.as_str())
+            .or_else(|| actor.profile.icon.as_ref().map(|icon| icon.url.as_str())),
+        large_image: image.is_some(),
+        published: Some(&published),
+    };
+    page_html(&title, &meta, &object.id, &body)
+}
+
+/// The page served in place of a deleted actor or object.
+pub fn gone_page() -> String {
+    let meta = Meta {
+        site_name: &config::CONFIG.primary_domain().domain,
+        og_type: "website",
+        title: "Gone",
+        description: "",
+        url: "",
+        image: None,
+        large_image: false,
+        published: None,
+    };
+    page_html("Gone", &meta, "", "<p>This has been deleted.</p>\n")
+}
+
+/// The page the subscribe template leads to: what `object` is and how to
+/// interact with it from another server, since there is no way to do so
+/// from here.
+pub fn interaction_page(object: &Value) -> String {
+    let id = object["id"].as_str().unwrap_or_default();
+    let is_actor = object["type"].as_str().is_some_and(is_actor_type);
+    let name = object["name"]
+        .as_str()
+        .or_else(|| object["preferredUsername"].as_str())
+        .unwrap_or(id);
+    let (title, action) = if is_actor {
+        (format!("Follow {}", name), "follow")
+    } else {
+        (
+            "Interact with this post".to_string(),
+            "reply to or boost it",
+        )
+    };
+    let description = object["content"]
+        .as_str()
+        .or_else(|| object["summary"].as_str())
+        .map(excerpt)
+        .unwrap_or_default();
+
+    let mut body = format!("<h1>{}</h1>\n", escape_html(&title));
+    if !description.is_empty() {
+        body.push_str(&format!(
+            "<blockquote>{}</blockquote>\n",
+            escape_html(&description)
+        ));
+    }
+    body.push_str(&format!(
+        "<p>To {}, search for this address from your own account:</p>\n<p><code>{}</code></p>\n",
+        action,
+        escape_html(id)
+    ));
+    let url = match &object["url"] {
+        Value::Object(link) => link.get("href").and_then(Value::as_str),
+        url => url.as_str(),
+    }
+    .unwrap_or(id);
+    if is_web_url(url) {
+        body.push_str(&format!(
+            "<p><a href=\"{}\" rel=\"nofollow noopener\">View it on its own server</a></p>\n",
+            escape_html(url)
+        ));
+    }
+
+    let meta = Meta {
+        site_name: &config::CONFIG.primary_domain().domain,
+        og_type: "website",
+        title: &title,
+        description: &description,
+        url: "",
+        image: None,
+        large_image: false,
+        published: None,
+    };
+    page_html(&title, &meta, "", &body)
+}
+
+/// A link to the post replied to, with a preview of it when we have it.
+fn reply_context(in_reply_to: &str, parent: Option<&Value>) -> String {
+    let url = parent
+        .and_then(|parent| parent["url"].as_str())
+        .filter(|url| is_web_url(url))
+        .or(Some(in_reply_to).filter(|url| is_web_url(url)));
+    let link = match url {
+        Some(url) => format!("<a href=\"{}\">In reply to</a>", escape_html(url)),
+        None => "In reply to".to_string(),
+    };
+    let preview = parent
+        .and_then(|parent| parent["content"].as_str())
+        .map(|content| {
+            format!(
+                "<blockquote>{}</blockquote>\n",
+                escape_html(&excerpt(content))
+            )
+        })
+        .unwrap_or_default();
+    format!(
+        "<aside class=\"reply-context\">\n{}\n{}</aside>\n",
+        link, preview
+    )
+}
+
+/// Whether a URL from another server is safe to link to.
+fn is_web_url(url: &str) -> bool {
+    url.starts_with("https://") || url.starts_with("http://")
+}
+
+/// A post with its attachments, poll and interaction counts. In a list of
+/// posts the date links to the post's own page.
+fn article(actor: &LocalActorPerson, object: &ObjectNote, linked: bool) -> String {
+    let heading = object
+        .name
+        .as_ref()
+        .map(|name| format!("<h1>{}</h1>\n", escape_html(name)))
+        .unwrap_or_default();
+    let date = format!(
+        "<time datetime=\"{}\">{}</time>",
+        object.published.to_rfc3339(),
+        object.published.format("%Y-%m-%d %H:%M")
+    );
+    let date = match linked {
+        true => format!(
+            "<a href=\"{}\">{}</a>",
+            escape_html(object.url.as_deref().unwrap_or(&object.id)),
+            date
+        ),
+        false => date,
+    };
+
+    // Content and summary were sanitized when the object was published.
+    let mut content = object.content.clone();
+    for attachment in &object.attachment {
+        content.push_str(&attachment_html(attachment));
+    }
+    content.push_str(&poll_html(object));
+    let content = match (&object.summary, object.sensitive) {
+        (Some(summary), Some(true)) => format!(
+            "<details>\n<summary>{}</summary>\n{}\n</details>",
+            summary, content
+        ),
+        (Some(summary), _) => format!("<div class=\"summary\">{}</div>\n{}", summary, content),
+        (None, _) => content,
+    };
+
+    let count = |collection: &Option<ObjectCollection>| {
+        collection
+            .as_ref()
+            .map(|collection| collection.total_items)
+            .unwrap_or_default()
+    };
+    format!(
+        r#"<article>
+{heading}<a href="{author_url}">{author}</a>
+{date}
+{content}
+<footer>{replies} replies · {shares} boosts · {likes} likes</footer>
+</article>
+"#,
+        heading = heading,
+        author_url = escape_html(&actor.actor_html_url()),
+        author = escape_html(&actor.name),
+        date = date,
+        content = content,
+        replies = count(&object.replies),
+        shares = count(&object.shares),
+        likes = count(&object.likes),
+    )
+}
+
+fn attachment_html(attachment: &ObjectDocument) -> String {
+    let url = escape_html(&attachment.url);
+    let alt = escape_html(attachment.name.as_deref().unwrap_or_default());
+    if attachment.media_type.starts_with("image/") {
+        format!("<img src=\"{}\" alt=\"{}\">\n", url, alt)
+    } else if attachment.media_type.starts_with("video/") {
+        format!(
+            "<video src=\"{}\" title=\"{}\" controls></video>\n",
+            url, alt
+        )
+    } else if attachment.media_type.starts_with("audio/") {
+        format!(
+            "<audio src=\"{}\" title=\"{}\" controls></audio>\n",
+            url, alt
+        )
+    } else {
+        format!("<a href=\"{}\">{}</a>\n", url, alt)
+    }
+}
+
+fn poll_html(object: &ObjectNote) -> String {
+    let options: Vec<_> = object.one_of.iter().chain(object.any_of.iter()).collect();
+    if options.is_empty() {
+        return String::new();
+    }
+    let mut html = String::from("<ul class=\"poll\">\n");
+    for option in options {
+        html.push_str(&format!(
+            "<li>{} ({})</li>\n",
+            escape_html(&option.name),
+            option.replies.total_items
+        ));
+    }
+    html.push_str("</ul>\n");
+    html
+}
+
+/// Plain text preview of an HTML fragment for link previews.
+fn excerpt(html: &str) -> String {
+    let text = to_plain_text(html)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    match text.char_indices().nth(DESCRIPTION_LENGTH) {
+        None => text,
+        Some((end, _)) => format!("{}…", &text[..end]),
+    }
+}
+
+/// What link previews show: OpenGraph and Twitter card tags.
+struct Meta<'a> {
+    site_name: &'a str,
+    og_type: &'a str,
+    title: &'a str,
+    description: &'a str,
+    url: &'a str,
+    image: Option<&'a str>,
+    /// Show the image as a large card rather than a thumbnail
+    large_image: bool,
+    published: Option<&'a str>,
+}
+
+impl Meta<'_> {
+    fn to_html(&self) -> String {
+        let mut tags = vec![
+            ("property", "og:site_name", self.site_name),
+            ("property", "og:type", self.og_type),
+            ("property", "og:title", self.title),
+        ];
+        if !self.url.is_empty() {
+            tags.push(("property", "og:url", self.url));
+        }
+        if !self.description.is_empty() {
+            tags.push(("name", "description", self.description));
+            tags.push(("property", "og:description", self.description));
+        }
+        if let Some(image) = self.image {
+            tags.push(("property", "og:image", image));
+        }
+        if let Some(published) = self.published {
+            tags.push(("property", "article:published_time", published));
+        }
+        let card = match self.large_image {
+            true => "summary_large_image",
+            false => "summary",
+        };
+        tags.push(("name", "twitter:card", card));
+        tags.push(("name", "twitter:title", self.title));
+
+        tags.iter()
+            .map(|(attribute, name, content)| {
+                format!(
+                    "<meta {}=\"{}\" content=\"{}\">\n",
+                    attribute,
+                    name,
+                    escape_html(content)
+                )
+            })
+            .collect()
+    }
+}
+
+fn page_html(title: &str, meta: &Meta, alternate: &str, body: &str) -> String {
+    let alternate = match alternate {
+        "" => String::new(),
+        id => format!(
+            "<link rel=\"alternate\" type=\"{}\" href=\"{}\">\n",
+            MEDIA_TYPE_ACTIVITY_JSON,
+            escape_html(id)
+        ),
+    };
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{title}</title>
+{meta}{alternate}</head>
+<body>
+{body}</body>
+</html>
+"#,
+        title = escape_html(title),
+        meta = meta.to_html(),
+        alternate = alternate,
+        body = body,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::ObjectImage;
+
+    #[test]
+    fn test_object_page_has_preview_tags() {
+        let actor = LocalActorPerson::new("a", ACTOR_TYPE_PERSON);
+        let mut note = ObjectNote::new(
+            "https://example.com/@a/notes/1",
+            &actor.actor_id(),
+            None,
+            "<p>Hello &amp; <b>welcome</b></p>",
+        );
+        note.attachment.push(ObjectDocument::new(
+            OBJECT_TYPE_IMAGE,
+            "image/png",
+            "https://example.com/media/x.png",
+            Some("a \"cat\""),
+        ));
+        note.attachment[0].icon = Some(ObjectImage::new("image/png", "https://example.com/t.png"));
+
+        let page = object_page(&actor, &note, None);
+        assert!(page.contains(r#"<meta property="og:description" content="Hello &amp; welcome">"#));
+        assert!(page
+            .contains(r#"<meta property="og:image" content="https://example.com/media/x.png">"#));
+        assert!(page.contains(r#"<meta name="twitter:card" content="summary_large_image">"#));
+        assert!(page.contains(r#"<link rel="alternate" type="application/activity+json" href="https://example.com/@a/notes/1">"#));
+        assert!(page.contains(r#"alt="a &quot;cat&quot;""#));
+
+        note.sensitive = Some(true);
+        note.summary = Some("spoilers".to_string());
+        let page = object_page(&actor, &note, None);
+        assert!(page.contains(r#"<meta property="og:description" content="spoilers">"#));
+        assert!(!page.contains("og:image"));
+    }
+
+    #[test]
+    fn test_reply_context_only_links_to_web_urls() {
+        let parent = serde_json::json!({
+            "url": "javascript:alert(1)",
+            "content": "<p>Hi</p>",
+        });
+        let context = reply_context("https://remote.example/notes/1", Some(&parent));
+        assert!(context.contains(r#"<a href="https://remote.example/notes/1">"#));
+        assert!(!context.contains("javascript:"));
+
+        let context = reply_context("javascript:alert(1)", Some(&parent));
+        assert!(!context.contains("<a "));
+        assert!(!context.contains("javascript:"));
+
+        let parent = serde_json::json!({"url": "https://remote.example/@b/1"});
+        let context = reply_context("https://remote.example/notes/1", Some(&parent));
+        assert!(context.contains(r#"<a href="https://remote.example/@b/1">"#));
+    }
+
+    #[test]
+    fn test_actor_page_links_pages() {
+        let actor = LocalActorPerson::new("a", ACTOR_TYPE_PERSON);
+        let page = actor_page(&actor, &[], 1, true);
+        assert!(page.contains(&format!(
+            r#"<a rel="prev" href="{}">"#,
+            actor.actor_html_url()
+        )));
+        assert!(page.contains(&format!(
+            r#"<a rel="next" href="{}?page=2">"#,
+            actor.actor_html_url()
+        )));
+        assert!(!actor_page(&actor, &[], 0, false).contains("<nav>"));
+    }
+}
@@ -0,0 +1,216 @@
+use std::io::Cursor;
+
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, ImageFormat, ImageOutputFormat};
+
+/// Longest side of generated thumbnails, in pixels.
+pub const THUMBNAIL_SIZE: u32 = 400;
+
+/// Blurhash components along the longer and shorter sides of an image.
+const BLURHASH_COMPONENTS: (u32, u32) = (4, 3);
+
+/// An uploaded image with its metadata removed, plus what we derived from it.
+#[derive(Debug)]
+pub struct ProcessedImage {
+    pub bytes: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    pub blurhash: Option<String>,
+    pub thumbnail: Thumbnail,
+}
+
+#[derive(Debug)]
+pub struct Thumbnail {
+    pub bytes: Vec<u8>,
+    pub media_type: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Decode an image, strip EXIF and other metadata from it, and produce a
+/// thumbnail and blurhash.
+///
+/// JPEG and PNG images are re-encoded after applying their EXIF orientation,
+/// which leaves no metadata behind. WebP images have their EXIF and XMP
+/// chunks removed, and GIFs are kept as they are to preserve animation, as
+/// the format has no EXIF.
+pub fn process_image(bytes: &[u8], media_type: &str) -> Result<ProcessedImage, ImagingError> {
+    let format = ImageFormat::from_mime_type(media_type).ok_or(ImagingError::Unsupported)?;
+    let image = image::load_from_memory_with_format(bytes, format)
+        .map_err(|_| ImagingError::Undecodable)?;
+
+    let (image, bytes) = match format {
+        ImageFormat::Jpeg | ImageFormat::Png => {
+            let image = apply_orientation(image, orientation(bytes));
+            let output_format = if format == ImageFormat::Jpeg {
+                ImageOutputFormat::Jpeg(90)
+            } else {
+                ImageOutputFormat::Png
+            };
+            let bytes = encode(&image, output_format)?;
+            (image, bytes)
+        }
+        ImageFormat::WebP => (image, strip_webp_metadata(bytes)?),
+        ImageFormat::Gif => (image, bytes.to_vec()),
+        _ => return Err(ImagingError::Unsupported),
+    };
+
+    let (width, height) = image.dimensions();
+    Ok(ProcessedImage {
+        bytes,
+        width,
+        height,
+        blurhash: blurhash(&image),
+        thumbnail: thumbnail(&image)?,
+    })
+}
+
+/// Value of the EXIF orientation tag, or 1 (upright) if there is none.
+fn orientation(bytes: &[u8]) -> u32 {
+    exif::Reader::new()
+        .read_from_container(&mut Cursor::new(bytes))
+        .ok()
+        .and_then(|exif| {
+            exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)
+                .and_then(|field| field.value.get_uint(0))
+        })
+        .unwrap_or(1)
+}
+
+fn apply_orientation(image: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+fn encode(image: &DynamicImage, format: ImageOutputFormat) -> Result<Vec<u8>, ImagingError> {
+    let mut bytes = Cursor::new(Vec::new());
+    // JPEG has no alpha channel, so flatten before encoding to it.
+    let result = match format {
+        ImageOutputFormat::Jpeg(_) => {
+            DynamicImage::ImageRgb8(image.to_rgb8()).write_to(&mut bytes, format)
+        }
+        _ => image.write_to(&mut bytes, format),
+    };
+    result.map_err(|_| ImagingError::Unencodable)?;
+    Ok(bytes.into_inner())
+}
+
+fn thumbnail(image: &DynamicImage) -> Result<Thumbnail, ImagingError> {
+    let thumbnail = image.resize(THUMBNAIL_SIZE, THUMBNAIL_SIZE, FilterType::Triangle);
+    let (bytes, media_type) = if thumbnail.color().has_alpha() {
+        (encode(&thumbnail, ImageOutputFormat::Png)?, "image/png")
+    } else {
+        (
+            encode(&thumbnail, ImageOutputFormat::Jpeg(80))?,
+            "image/jpeg",
+        )
+    };
+    Ok(Thumbnail {
+        bytes,
+        media_type: media_type.to_string(),
+        width: thumbnail.width(),
+        height: thumbnail.height(),
+    })
+}
+
+fn blurhash(image: &DynamicImage) -> Option<String> {
+    // The hash only captures coarse colour, so a small copy is plenty.
+    let small = image.thumbnail(64, 64).to_rgba8();
+    let (x, y) = if small.width() >= small.height() {
+        BLURHASH_COMPONENTS
+    } else {
+        (BLURHASH_COMPONENTS.1, BLURHASH_COMPONENTS.0)
+    };
+    blurhash::encode(x, y, small.width(), small.height(), small.as_raw()).ok()
+}
+
+/// Remove EXIF and XMP chunks from a WebP file, clearing the matching flags
+/// in its VP8X header.
+fn strip_webp_metadata(bytes: &[u8]) -> Result<Vec<u8>, ImagingError> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WEBP" {
+        return Err(ImagingError::Undecodable);
+    }
+
+    let mut output = bytes[0..12].to_vec();
+    let mut offset = 12;
+    while offset + 8 <= bytes.len() {
+        let fourcc = &bytes[offset..offset + 4];
+        let size = u32::from_le_bytes(bytes[offset + 4..offset + 8].try_into().unwrap()) as usize;
+        // Chunks are padded to an even length.
+        let end = (offset + 8 + size + size % 2).min(bytes.len());
+        match fourcc {
+            b"EXIF" | b"XMP " => {}
+            b"VP8X" => {
+                let mut chunk = bytes[offset..end].to_vec();
+                if chunk.len() > 8 {
+                    // Bit 3 flags EXIF, bit 2 flags XMP.
+                    chunk[8] &= !0b0000_1100;
+                }
+                output.extend_from_slice(&chunk);
+            }
+            _ => output.extend_from_slice(&bytes[offset..end]),
+        }
+        offset = end;
+    }
+
+    let riff_size = (output.len() - 8) as u32;
+    output[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Ok(output)
+}
+
+/// An error that occured while processing an image.
+#[derive(Debug, PartialEq)]
+pub enum ImagingError {
+    /// The media type is not an image format we process.
+    Unsupported,
+    /// The bytes could not be decoded as the claimed format.
+    Undecodable,
+    /// The processed image could not be encoded again.
+    Unencodable,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgb};
+
+    #[test]
+    fn test_process_png_reports_dimensions_and_thumbnail() {
+        let image = DynamicImage::ImageRgb8(ImageBuffer::from_pixel(800, 200, Rgb([200, 30, 30])));
+        let png = encode(&image, ImageOutputFormat::Png).unwrap();
+
+        let processed = process_image(&png, "image/png").expect("processing to succeed");
+        assert_eq!((processed.width, processed.height), (800, 200));
+        assert_eq!(
+            (processed.thumbnail.width, processed.thumbnail.height),
+            (400, 100)
+        );
+        assert_eq!(processed.thumbnail.media_type, "image/jpeg");
+        assert!(processed.blurhash.is_some());
+    }
+
+    #[test]
+    fn test_strip_webp_metadata_drops_exif_chunk() {
+        let mut webp = b"RIFF\0\0\0\0WEBP".to_vec();
+        webp.extend_from_slice(b"VP8X\x0a\0\0\0\x08\0\0\0\0\0\0\0\0\0");
+        webp.extend_from_slice(b"EXIF\x03\0\0\0abc\0");
+        webp.extend_from_slice(b"ALPH\x02\0\0\0xy");
+
+        let stripped = strip_webp_metadata(&webp).unwrap();
+        assert!(!stripped.windows(4).any(|window| window == b"EXIF"));
+        assert!(stripped.windows(4).any(|window| window == b"ALPH"));
+        assert_eq!(stripped[20], 0);
+        assert_eq!(
+            u32::from_le_bytes(stripped[4..8].try_into().unwrap()) as usize,
+            stripped.len() - 8
+        );
+    }
+}
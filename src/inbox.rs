@@ -0,0 +1,609 @@
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+use log::{debug, info, warn};
+use serde::Deserialize;
+use serde_json::Value;
+use url::Url;
+
+use crate::activities::{self, Activity};
+use crate::actors;
+use crate::app::AppState;
+use crate::authorized_fetch::{self, AuthorizationError};
+use crate::config;
+use crate::constants::*;
+use crate::fetch;
+use crate::jsonld;
+use crate::media;
+use crate::model::{OneOrMany, Reference};
+use crate::moderation;
+use crate::objects::ObjectNote;
+use crate::remote_actors;
+use crate::sanitize;
+use crate::store::{Interaction, StoredObject};
+
+#[post("/inbox")]
+pub async fn shared_inbox_service(
+    req: HttpRequest,
+    body: web::Bytes,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    respond(&data, &req, &body).await
+}
+
+#[post("/@{name}/inbox")]
+pub async fn inbox_service(
+    path: web::Path<String>,
+    req: HttpRequest,
+    body: web::Bytes,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    match actors::actor_lookup(&data, &path.into_inner()) {
+        Err(actors::ResolverError::NotFound) => return HttpResponse::NotFound().finish(),
+        Err(actors::ResolverError::Gone(_)) => return HttpResponse::Gone().finish(),
+        Ok(_) => (),
+    }
+    respond(&data, &req, &body).await
+}
+
+async fn respond(data: &AppState, req: &HttpRequest, body: &[u8]) -> HttpResponse {
+    // Nothing in the body can be trusted until we know who sent it.
+    let signer = match authorized_fetch::signed_post_by(data, req, body).await {
+        // Rejected servers are dropped quietly; an error would only make
+        // them retry.
+        Err(AuthorizationError::Blocked) => {
+            debug!("Dropped activity signed by a rejected server");
+            return HttpResponse::Accepted().finish();
+        }
+        Err(err) => return err.response(),
+        Ok(signer) => signer,
+    };
+    // Activities arrive as application/activity+json, which the Json
+    // extractor refuses, so parse the raw body instead.
+    let result = serde_json::from_slice::<Value>(body)
+        .and_then(|mut activity| {
+            jsonld::normalize(&mut activity);
+            serde_json::from_value::<InboundActivity>(activity)
+        })
+        .map_err(|_| InboxError::Invalid)
+        .and_then(|activity| match activity.actor.id() {
+            Some(actor) if actor == signer => handle_activity(data, &activity),
+            _ => Err(InboxError::Unauthorized),
+        });
+    match result {
+        Ok(()) => HttpResponse::Accepted().finish(),
+        Err(InboxError::Invalid) => HttpResponse::BadRequest().finish(),
+        Err(InboxError::Unauthorized) => HttpResponse::Unauthorized().finish(),
+        Err(InboxError::Forbidden) => HttpResponse::Forbidden().finish(),
+        Err(InboxError::NotFound) => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// An activity from another server, whose object may be embedded or given by
+/// id.
+pub type InboundActivity = Activity<Reference<Value>>;
+
+/// Apply an activity received from another server.
+pub fn handle_activity(data: &AppState, activity: &InboundActivity) -> Result<(), InboxError> {
+    let activity_type = activity.activity_type.as_str();
+    let actor = activity.actor.id().ok_or(InboxError::Invalid)?;
+    let object_id = activity.object.id().ok_or(InboxError::Invalid)?;
+    debug!("Inbox received {} from {}", activity_type, actor);
+
+    if activity_type == ACTIVITY_TYPE_CREATE {
+        let object = activity.object.object().ok_or(InboxError::Invalid)?.clone();
+        if !same_origin(actor, object_id) {
+            return Err(InboxError::Forbidden);
+        }
+        if let Some(question) = poll_replied_to(data, &object) {
+            if property_ids(&object["attributedTo"]) != [actor] {
+                return Err(InboxError::Forbidden);
+            }
+            let choice = object["name"].as_str().unwrap_or_default();
+            return vote(data, actor, &question, choice);
+        }
+        if !object["inReplyTo"].is_null() {
+            actix_web::rt::spawn(cache_reply_chain(data.clone(), object.clone()));
+        }
+        cache_object(data, object_id, object);
+        rebroadcast(data, activity, object_id);
+    } else if activity_type == ACTIVITY_TYPE_ANNOUNCE {
+        match data.store.get_object(object_id) {
+            Some(StoredObject::Note(_)) => (),
+            Some(StoredObject::Tombstone(_)) => return Err(InboxError::NotFound),
+            None if data.store.get_remote_object(object_id).is_some() => (),
+            None => match activity.object.object() {
+                // Only the object's own server can vouch for it, so an
+                // embedded copy from anywhere else is fetched again.
+                Some(object) if same_origin(actor, object_id) => {
+                    cache_object(data, object_id, object.clone())
+                }
+                _ => {
+                    actix_web::rt::spawn(fetch_and_cache(data.clone(), object_id.to_string()));
+                }
+            },
+        }
+        data.store
+            .add_interaction(Interaction::Announce, object_id, actor, &activity.id);
+    } else if activity_type == ACTIVITY_TYPE_DELETE {
+        if !same_origin(actor, object_id) {
+            return Err(InboxError::Forbidden);
+        }
+        if data.store.purge_remote_object(object_id) {
+            info!("Purged deleted remote object {}", object_id);
+        }
+    } else if activity_type == ACTIVITY_TYPE_LIKE {
+        if !matches!(
+            data.store.get_object(object_id),
+            Some(StoredObject::Note(_))
+        ) {
+            return Err(InboxError::NotFound);
+        }
+        data.store
+            .add_interaction(Interaction::Like, object_id, actor, &activity.id);
+    } else if activity_type == ACTIVITY_TYPE_UPDATE {
+        let object_type = activity.object.object().and_then(|object| object["type"].as_str());
+        if object_type.is_some_and(remote_actors::is_actor_type) {
+            // Actors may only update themselves. The embedded copy is not
+            // signed by anyone, so the actor is fetched again from its server.
+            if object_id != actor {
+                return Err(InboxError::Forbidden);
+            }
+            actix_web::rt::spawn(remote_actors::refresh_remote_actor(
+                data.clone(),
+                actor.to_string(),
+            ));
+        }
+    } else if activity_type == ACTIVITY_TYPE_UNDO {
+        undo(data, actor, &activity.object)?;
+    }
+
+    Ok(())
+}
+
+/// Reverse an earlier activity by `actor`, given inline or by id.
+fn undo(data: &AppState, actor: &str, object: &Reference<Value>) -> Result<(), InboxError> {
+    let object = match object {
+        Reference::Iri(activity_id) => {
+            // Only likes and announces are remembered by activity id.
+            data.store.remove_interaction_activity(actor, activity_id);
+            return Ok(());
+        }
+        Reference::Object(object) => object,
+    };
+
+    if property_ids(&object["actor"]) != [actor] {
+        return Err(InboxError::Forbidden);
+    }
+    let object_type = object["type"].as_str().ok_or(InboxError::Invalid)?;
+    let kind = match Interaction::from_activity_type(object_type) {
+        Some(kind) => kind,
+        None => return Ok(()),
+    };
+    let undone_id = property_ids(&object["object"])
+        .pop()
+        .ok_or(InboxError::Invalid)?;
+    data.store.remove_interaction(kind, &undone_id, actor);
+    Ok(())
+}
+
+/// The local poll an object is a vote on, if it is one: votes are Notes
+/// replying to the poll with the chosen option as their `name`.
+fn poll_replied_to(data: &AppState, object: &Value) -> Option<ObjectNote> {
+    object["name"].as_str()?;
+    match data.store.get_object(object["inReplyTo"].as_str()?)? {
+        StoredObject::Note(question) if question.object_type == OBJECT_TYPE_QUESTION => {
+            Some(*question)
+        }
+        _ => None,
+    }
+}
+
+/// Count a vote on a local poll and let everyone know the new results.
+fn vote(
+    data: &AppState,
+    actor: &str,
+    question: &ObjectNote,
+    choice: &str,
+) -> Result<(), InboxError> {
+    if !question.is_open_poll() {
+        return Err(InboxError::Forbidden);
+    }
+    if !question.has_poll_option(choice) {
+        return Err(InboxError::Invalid);
+    }
+    let multiple = !question.any_of.is_empty();
+    if !data.store.add_vote(&question.id, actor, choice, multiple) {
+        return Ok(());
+    }
+    let author =
+        actors::local_actor_by_id(data, &question.attributed_to).ok_or(InboxError::NotFound)?;
+    actix_web::rt::spawn(send_results(
+        data.clone(),
+        author.name,
+        question.id.clone(),
+        actor.to_string(),
+    ));
+    Ok(())
+}
+
+/// Add a voter to the poll's recipients, so they hear about this and later
+/// results, and send the results out.
+async fn send_results(data: AppState, author_name: String, question_id: String, voter: String) {
+    match remote_actors::remote_actor(&data, &voter).await {
+        Ok(actor) => data.store.add_recipients(
+            &author_name,
+            &question_id,
+            &[actor.delivery_inbox().to_string()],
+        ),
+        Err(err) => warn!("Could not find the inbox of voter {}: {:?}", voter, err),
+    }
+    if let Ok(author) = actors::actor_lookup(&data, &author_name) {
+        activities::send_poll_update(&data, &author, &question_id);
+    }
+}
+
+/// Sanitize and cache a remote object, proxying its media if configured to.
+/// Objects from rejected domains are not cached at all, and media from
+/// domains whose media we reject is dropped rather than shown.
+fn cache_object(data: &AppState, object_id: &str, mut object: Value) {
+    if moderation::rejects(&data.store, object_id) {
+        return;
+    }
+    sanitize::sanitize_object(&mut object);
+    let rejects_media = moderation::rejects_media(&data.store, object_id);
+    if rejects_media {
+        if let Some(object) = object.as_object_mut() {
+            object.remove("attachment");
+            object.remove("image");
+        }
+    }
+    data.store.put_remote_object(object_id, object);
+    if config::CONFIG.media_proxy && !rejects_media {
+        actix_web::rt::spawn(media::proxy_remote_media(
+            data.clone(),
+            object_id.to_string(),
+        ));
+    }
+}
+
+/// Dereference an object we only know by id and cache it, provided the copy
+/// we get is really the object we asked for.
+async fn fetch_and_cache(data: AppState, object_id: String) {
+    match fetch::fetch_object(&object_id).await {
+        Err(err) => warn!("Could not fetch announced object {}: {:?}", object_id, err),
+        Ok(object) if object["id"].as_str() != Some(object_id.as_str()) => {
+            warn!("Fetched object does not match requested id {}", object_id)
+        }
+        Ok(object) => cache_object(&data, &object_id, object),
+    }
+}
+
+/// Cache the posts a reply is replying to that we haven't seen yet, so the
+/// thread can be shown.
+async fn cache_reply_chain(data: AppState, object: Value) {
+    match fetch::fetch_reply_chain(&data.store, &object).await {
+        Ok(chain) => {
+            for (parent_id, parent) in chain {
+                cache_object(&data, &parent_id, parent);
+            }
+        }
+        Err(err) => warn!(
+            "Could not resolve the replies above {}: {:?}",
+            object["id"], err
+        ),
+    }
+}
+
+/// Have every local Group the activity is addressed to announce its object.
+/// Only public posts are rebroadcast; announcing a followers-only or direct
+/// one would show it to all of the group's followers.
+fn rebroadcast(data: &AppState, activity: &InboundActivity, object_id: &str) {
+    let object = activity.object.object().cloned().unwrap_or_default();
+    let activity_is_public = activity
+        .to
+        .iter()
+        .chain(activity.cc.iter())
+        .any(|id| jsonld::is_public(id));
+    if !activity_is_public || !jsonld::is_addressed_to_public(&object) {
+        return;
+    }
+    let mut addressed: Vec<String> = activity
+        .to
+        .iter()
+        .chain(activity.cc.iter())
+        .cloned()
+        .collect();
+    addressed.extend(property_ids(&object["to"]));
+    addressed.extend(property_ids(&object["cc"]));
+    for actor_id in addressed {
+        let group = match actors::local_actor_by_id(data, &actor_id) {
+            Some(group) if group.is_group() => group,
+            _ => continue,
+        };
+        if activities::share(data, &group, object_id, None).is_ok() {
+            info!("{} rebroadcast {}", group.name, object_id);
+        }
+    }
+}
+
+/// The ids in a property that may hold one or many IRIs or objects.
+pub fn property_ids(value: &Value) -> Vec<String> {
+    OneOrMany::<Reference<Value>>::deserialize(value)
+        .map(|references| {
+            references
+                .iter()
+                .filter_map(Reference::id)
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Whether two URLs share a scheme, host and port.
+pub fn same_origin(a: &str, b: &str) -> bool {
+    match (Url::parse(a), Url::parse(b)) {
+        (Ok(a), Ok(b)) => a.origin() == b.origin(),
+        _ => false,
+    }
+}
+
+/// An error that occured while handling an incoming activity.
+#[derive(Debug, PartialEq)]
+pub enum InboxError {
+    /// The activity was missing required properties.
+    Invalid,
+    /// The activity was not signed by its actor.
+    Unauthorized,
+    /// The actor is not allowed to act on the object.
+    Forbidden,
+    /// The activity refers to a local object that does not exist.
+    NotFound,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delivery::DeliveryQueue;
+    use crate::http_signatures::{
+        self, tests::PRIVATE_KEY_PKCS1_PEM, tests::PUBLIC_KEY_PKCS8_PEM,
+    };
+    use crate::remote_actors::RemoteActor;
+    use crate::store::Store;
+    use crate::webfinger::WebfingerClient;
+    use actix_web::test as actix_test;
+    use chrono::Utc;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    const ACTOR: &str = "https://remote.example/users/b";
+
+    fn state() -> AppState {
+        let data = AppState {
+            public_key: String::new(),
+            store: Arc::new(Store::new()),
+            delivery: DeliveryQueue::start(),
+            webfinger: Arc::new(WebfingerClient::default()),
+        };
+        let actor = json!({
+            "id": ACTOR,
+            "type": "Person",
+            "inbox": format!("{}/inbox", ACTOR),
+            "publicKey": {
+                "id": format!("{}#main-key", ACTOR),
+                "owner": ACTOR,
+                "publicKeyPem": PUBLIC_KEY_PKCS8_PEM,
+            },
+        });
+        data.store
+            .put_remote_actor(RemoteActor::from_value(&actor, Utc::now()).unwrap());
+        data
+    }
+
+    /// A POST to the shared inbox, signed by `ACTOR` over `signed_body`.
+    fn signed_post(signed_body: &str) -> HttpRequest {
+        let headers = http_signatures::sign_request(
+            PRIVATE_KEY_PKCS1_PEM,
+            &format!("{}#main-key", ACTOR),
+            "POST",
+            "https://example.com/inbox",
+            Some(signed_body.as_bytes()),
+        )
+        .unwrap();
+        headers
+            .into_iter()
+            .fold(
+                actix_test::TestRequest::post().uri("/inbox"),
+                |request, header| request.insert_header(header),
+            )
+            .to_http_request()
+    }
+
+    /// Deliver an activity signed by `ACTOR`, returning the response status.
+    async fn deliver(data: &AppState, activity: Value) -> u16 {
+        let body = activity.to_string();
+        respond(data, &signed_post(&body), body.as_bytes())
+            .await
+            .status()
+            .as_u16()
+    }
+
+    fn local_actor(data: &AppState, name: &str, actor_type: &str) -> actors::LocalActorPerson {
+        let domain = &config::CONFIG.primary_domain().domain;
+        data.store.register_actor(name, actor_type, domain).unwrap();
+        actors::actor_lookup(data, name).unwrap()
+    }
+
+    #[test]
+    fn test_property_ids() {
+        assert_eq!(
+            property_ids(&json!(["https://a.example/x", "https://b.example/y"])),
+            ["https://a.example/x", "https://b.example/y"]
+        );
+        assert_eq!(
+            property_ids(&json!([{"id": "https://a.example/x"}, "https://b.example/y"])),
+            ["https://a.example/x", "https://b.example/y"]
+        );
+        assert_eq!(property_ids(&json!("https://a.example/x")), ["https://a.example/x"]);
+        assert_eq!(property_ids(&json!({"id": "https://a.example/x"})), ["https://a.example/x"]);
+        assert!(property_ids(&Value::Null).is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_inbox_requires_signature_from_actor() {
+        let data = state();
+        let object_id = "https://remote.example/notes/1";
+        data.store
+            .put_remote_object(object_id, json!({"id": object_id, "type": "Note"}));
+        let delete = |actor: &str| {
+            json!({
+                "id": "https://remote.example/deletes/1",
+                "type": "Delete",
+                "actor": actor,
+                "object": object_id,
+            })
+            .to_string()
+        };
+
+        let unsigned = actix_test::TestRequest::post()
+            .uri("/inbox")
+            .to_http_request();
+        let forged = delete("https://remote.example/users/c");
+        let tampered = delete(ACTOR);
+        for (req, body) in [
+            (unsigned, delete(ACTOR)),
+            (signed_post(&forged), forged.clone()),
+            (signed_post(&forged), tampered),
+        ] {
+            let response = respond(&data, &req, body.as_bytes()).await;
+            assert_eq!(response.status(), 401);
+        }
+        assert!(data.store.get_remote_object(object_id).is_some());
+
+        let body = delete(ACTOR);
+        let response = respond(&data, &signed_post(&body), body.as_bytes()).await;
+        assert_eq!(response.status(), 202);
+        assert!(data.store.get_remote_object(object_id).is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_inbox_drops_activities_signed_from_rejected_domains() {
+        let data = state();
+        data.store.put_domain_policy(moderation::DomainPolicy {
+            domain: "remote.example".to_string(),
+            severity: moderation::Severity::Reject,
+            reject_media: false,
+            public_comment: None,
+        });
+        let object_id = "https://remote.example/notes/1";
+        data.store
+            .put_remote_object(object_id, json!({"id": object_id, "type": "Note"}));
+        let delete = json!({
+            "id": "https://remote.example/deletes/1",
+            "type": "Delete",
+            "actor": ACTOR,
+            "object": object_id,
+        });
+        assert_eq!(deliver(&data, delete).await, 202);
+        assert!(data.store.get_remote_object(object_id).is_some());
+    }
+
+    #[actix_web::test]
+    async fn test_announce_and_undo_announce() {
+        let data = state();
+        let actor = local_actor(&data, "a", ACTOR_TYPE_PERSON);
+        let note = activities::publish_note(&data, &actor, Default::default()).await;
+        let announce = |id: &str| {
+            json!({
+                "id": id,
+                "type": "Announce",
+                "actor": ACTOR,
+                "object": note.id,
+                "to": TO_PUBLIC,
+            })
+        };
+        let shares = || data.store.interactions(Interaction::Announce, &note.id);
+
+        let first = announce("https://remote.example/announces/1");
+        assert_eq!(deliver(&data, first.clone()).await, 202);
+        assert_eq!(shares(), [ACTOR]);
+
+        // Undone by id
+        let undo = |id: &str, object: Value| {
+            json!({"id": id, "type": "Undo", "actor": ACTOR, "object": object})
+        };
+        let undo_first = undo("https://remote.example/undos/1", first["id"].clone());
+        assert_eq!(deliver(&data, undo_first).await, 202);
+        assert!(shares().is_empty());
+
+        // Undone with the Announce embedded, which must be the signer's own
+        let second = announce("https://remote.example/announces/2");
+        assert_eq!(deliver(&data, second.clone()).await, 202);
+        let mut foreign = second.clone();
+        foreign["actor"] = json!("https://remote.example/users/c");
+        let undo_foreign = undo("https://remote.example/undos/2", foreign);
+        assert_eq!(deliver(&data, undo_foreign).await, 403);
+        assert_eq!(shares(), [ACTOR]);
+        assert_eq!(
+            deliver(&data, undo("https://remote.example/undos/3", second)).await,
+            202
+        );
+        assert!(shares().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_groups_rebroadcast_posts_addressed_to_them() {
+        let data = state();
+        let group = local_actor(&data, "g", ACTOR_TYPE_GROUP);
+        let person = local_actor(&data, "p", ACTOR_TYPE_PERSON);
+        let create = |n: u32, to: Vec<String>| {
+            json!({
+                "id": format!("https://remote.example/creates/{}", n),
+                "type": "Create",
+                "actor": ACTOR,
+                "to": to,
+                "object": {
+                    "id": format!("https://remote.example/notes/{}", n),
+                    "type": "Note",
+                    "attributedTo": ACTOR,
+                    "content": "<p>Hi</p>",
+                    "to": to,
+                },
+            })
+        };
+        let public = TO_PUBLIC.to_string();
+
+        let addressed = create(1, vec![public.clone(), group.actor_id()]);
+        assert_eq!(deliver(&data, addressed).await, 202);
+        let note_id = "https://remote.example/notes/1";
+        assert_eq!(
+            data.store.interactions(Interaction::Announce, note_id),
+            [group.actor_id()]
+        );
+        let outbox = data.store.outbox("g");
+        assert_eq!(outbox.len(), 1);
+        assert_eq!(outbox[0]["type"], ACTIVITY_TYPE_ANNOUNCE);
+        assert_eq!(outbox[0]["object"], note_id);
+
+        // Only groups rebroadcast, and only what is addressed to them.
+        let elsewhere = create(2, vec![public, person.actor_id()]);
+        assert_eq!(deliver(&data, elsewhere).await, 202);
+        let note_id = "https://remote.example/notes/2";
+        assert!(data.store.get_remote_object(note_id).is_some());
+        assert!(data
+            .store
+            .interactions(Interaction::Announce, note_id)
+            .is_empty());
+        assert!(data.store.outbox("p").is_empty());
+
+        // Nor are posts that aren't public.
+        let followers = format!("{}/followers", ACTOR);
+        let private = create(3, vec![followers, group.actor_id()]);
+        assert_eq!(deliver(&data, private).await, 202);
+        let note_id = "https://remote.example/notes/3";
+        assert!(data.store.get_remote_object(note_id).is_some());
+        assert!(data
+            .store
+            .interactions(Interaction::Announce, note_id)
+            .is_empty());
+        assert_eq!(data.store.outbox("g").len(), 1);
+    }
+}
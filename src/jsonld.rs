@@ -0,0 +1,323 @@
+use std::collections::BTreeSet;
+
+use actix_web::http::header;
+use actix_web::{HttpRequest, HttpResponse, HttpResponseBuilder};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::constants::*;
+use crate::model::{Context, OneOrMany};
+
+/// Namespaces of the extension vocabularies we use, by prefix.
+const PREFIXES: &[(&str, &str)] = &[
+    ("as", "https://www.w3.org/ns/activitystreams#"),
+    ("ostatus", "http://ostatus.org#"),
+    ("schema", "http://schema.org#"),
+    ("toot", "http://joinmastodon.org/ns#"),
+];
+
+/// Terms that are not part of the ActivityStreams context, with the prefix
+/// they live under. A term is defined in a document's context only when the
+/// document uses it, as a property or a type.
+const EXTENSION_TERMS: &[(&str, &str)] = &[
+    ("manuallyApprovesFollowers", "as"),
+    ("sensitive", "as"),
+    ("Hashtag", "as"),
+    ("conversation", "ostatus"),
+    ("PropertyValue", "schema"),
+    ("value", "schema"),
+    ("Emoji", "toot"),
+    ("blurhash", "toot"),
+    ("discoverable", "toot"),
+    ("votersCount", "toot"),
+];
+
+/// Properties holding the audience of an object.
+const ADDRESSING: &[&str] = &["to", "cc", "bto", "bcc", "audience"];
+
+/// Serialize a document with an `@context` describing exactly the
+/// vocabularies it uses.
+pub fn with_context<T: Serialize>(document: &T) -> Value {
+    let mut document = serde_json::to_value(document).unwrap_or_default();
+    if let Value::Object(map) = &mut document {
+        map.remove("@context");
+        let context = build_context(&Value::Object(map.clone()));
+        map.insert(
+            "@context".to_string(),
+            serde_json::to_value(context).unwrap_or_default(),
+        );
+    }
+    document
+}
+
+/// Like `with_context`, pretty-printed for serving.
+pub fn to_string<T: Serialize>(document: &T) -> String {
+    serde_json::to_string_pretty(&with_context(document)).unwrap()
+}
+
+/// Serve a document as ActivityStreams JSON of the given media type.
+pub fn respond<T: Serialize>(
+    mut response: HttpResponseBuilder,
+    media_type: &str,
+    document: &T,
+) -> HttpResponse {
+    response
+        .insert_header((header::CONTENT_TYPE, media_type))
+        .body(to_string(document))
+}
+
+/// The ActivityStreams media type a request asks for in its `Accept` header,
+/// or `None` if it should be served HTML. Ranges are taken in the order they
+/// are listed, skipping any the client refuses with `q=0`.
+pub fn negotiate(req: &HttpRequest) -> Option<&'static str> {
+    let accept = req.headers().get(header::ACCEPT)?.to_str().ok()?;
+    accept.split(',').find_map(|range| {
+        let mut params = range.split(';').map(str::trim);
+        let media_type = params.next()?.to_ascii_lowercase();
+        let refused = params.any(|param| {
+            param
+                .strip_prefix("q=")
+                .and_then(|q| q.parse::<f32>().ok())
+                .is_some_and(|q| q == 0.0)
+        });
+        if refused {
+            return None;
+        }
+        match media_type.as_str() {
+            "application/activity+json" | "application/json" => Some(MEDIA_TYPE_ACTIVITY_JSON),
+            "application/ld+json" => Some(MEDIA_TYPE_LD_JSON),
+            _ => None,
+        }
+    })
+}
+
+/// The `@context` for a document: ActivityStreams, the security vocabulary
+/// if it carries a public key, and definitions for any extension terms.
+pub fn build_context(document: &Value) -> Context {
+    let mut terms = BTreeSet::new();
+    collect_terms(document, &mut terms);
+
+    let mut contexts = vec![Value::from(CONTEXT_ACTIVITYSTREAMS)];
+    if terms.contains("publicKey") {
+        contexts.push(Value::from(CONTEXT_SECURITY));
+    }
+
+    let mut definitions = Map::new();
+    for (term, prefix) in EXTENSION_TERMS {
+        if !terms.contains(*term) {
+            continue;
+        }
+        if let Some((_, namespace)) = PREFIXES.iter().find(|(known, _)| known == prefix) {
+            definitions.insert(prefix.to_string(), Value::from(*namespace));
+        }
+        definitions.insert(
+            term.to_string(),
+            Value::from(format!("{}:{}", prefix, term)),
+        );
+    }
+    if !definitions.is_empty() {
+        contexts.push(Value::Object(definitions));
+    }
+
+    match contexts.len() {
+        1 => OneOrMany::One(contexts.remove(0)),
+        _ => OneOrMany::Many(contexts),
+    }
+}
+
+/// Property names and type names used anywhere in a document.
+fn collect_terms(value: &Value, terms: &mut BTreeSet<String>) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                if key == "@context" {
+                    continue;
+                }
+                if key == "type" {
+                    match value {
+                        Value::String(name) => {
+                            terms.insert(name.clone());
+                        }
+                        Value::Array(names) => {
+                            terms.extend(names.iter().filter_map(Value::as_str).map(str::to_string))
+                        }
+                        _ => (),
+                    }
+                }
+                terms.insert(key.clone());
+                collect_terms(value, terms);
+            }
+        }
+        Value::Array(values) => values.iter().for_each(|value| collect_terms(value, terms)),
+        _ => (),
+    }
+}
+
+/// Rewrite an inbound document into the compact terms we match against:
+/// prefixed or expanded property and type names become bare terms, and
+/// every spelling of the public collection becomes `TO_PUBLIC`.
+pub fn normalize(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            let context = map.remove("@context");
+            let entries = std::mem::take(map);
+            for (key, mut value) in entries {
+                let key = compact_term(&key);
+                if key == "type" {
+                    compact_types(&mut value);
+                } else if ADDRESSING.contains(&key.as_str()) {
+                    normalize_public(&mut value);
+                }
+                normalize(&mut value);
+                map.insert(key, value);
+            }
+            if let Some(context) = context {
+                map.insert("@context".to_string(), context);
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(normalize),
+        _ => (),
+    }
+}
+
+/// Whether an addressing entry names the public collection, in any of the
+/// ways servers write it.
+pub fn is_public(id: &str) -> bool {
+    id == TO_PUBLIC || id == "as:Public" || id == "Public"
+}
+
+/// Whether an object's `to` or `cc` names the public collection.
+pub fn is_addressed_to_public(object: &Value) -> bool {
+    ["to", "cc"]
+        .iter()
+        .any(|property| match &object[*property] {
+            Value::String(id) => is_public(id),
+            Value::Array(ids) => ids.iter().filter_map(Value::as_str).any(is_public),
+            _ => false,
+        })
+}
+
+fn compact_term(term: &str) -> String {
+    for (prefix, namespace) in PREFIXES {
+        if let Some(name) = term
+            .strip_prefix(namespace)
+            .or_else(|| term.strip_prefix(&format!("{}:", prefix)))
+        {
+            if !name.is_empty() {
+                return name.to_string();
+            }
+        }
+    }
+    term.to_string()
+}
+
+fn compact_types(value: &mut Value) {
+    match value {
+        Value::String(name) => *name = compact_term(name),
+        Value::Array(names) => names.iter_mut().for_each(compact_types),
+        _ => (),
+    }
+}
+
+fn normalize_public(value: &mut Value) {
+    match value {
+        Value::String(id) if is_public(id) => *id = TO_PUBLIC.to_string(),
+        Value::Array(ids) => ids.iter_mut().for_each(normalize_public),
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use serde_json::json;
+
+    fn accepting(accept: &str) -> Option<&'static str> {
+        negotiate(
+            &TestRequest::default()
+                .insert_header((header::ACCEPT, accept))
+                .to_http_request(),
+        )
+    }
+
+    #[test]
+    fn test_negotiate_media_type() {
+        assert_eq!(
+            accepting("application/activity+json"),
+            Some(MEDIA_TYPE_ACTIVITY_JSON)
+        );
+        assert_eq!(
+            accepting(r#"application/ld+json; profile="https://www.w3.org/ns/activitystreams""#),
+            Some(MEDIA_TYPE_LD_JSON)
+        );
+        assert_eq!(accepting("text/html,application/xhtml+xml,*/*;q=0.8"), None);
+        assert_eq!(accepting("application/activity+json;q=0, text/html"), None);
+        assert_eq!(negotiate(&TestRequest::default().to_http_request()), None);
+    }
+
+    #[test]
+    fn test_context_only_defines_terms_in_use() {
+        let note = json!({"id": "https://example.com/1", "type": "Note", "content": "hi"});
+        assert_eq!(
+            serde_json::to_value(build_context(&note)).unwrap(),
+            json!(CONTEXT_ACTIVITYSTREAMS)
+        );
+
+        let actor = json!({
+            "type": "Person",
+            "manuallyApprovesFollowers": false,
+            "publicKey": {"id": "https://example.com/a#main-key"},
+            "attachment": [{"type": "PropertyValue", "name": "Site", "value": "x"}],
+            "tag": [{"type": "Emoji", "name": ":x:"}]
+        });
+        assert_eq!(
+            serde_json::to_value(build_context(&actor)).unwrap(),
+            json!([
+                CONTEXT_ACTIVITYSTREAMS,
+                CONTEXT_SECURITY,
+                {
+                    "as": "https://www.w3.org/ns/activitystreams#",
+                    "manuallyApprovesFollowers": "as:manuallyApprovesFollowers",
+                    "schema": "http://schema.org#",
+                    "PropertyValue": "schema:PropertyValue",
+                    "value": "schema:value",
+                    "toot": "http://joinmastodon.org/ns#",
+                    "Emoji": "toot:Emoji"
+                }
+            ])
+        );
+    }
+
+    #[test]
+    fn test_normalize_treats_public_aliases_alike() {
+        let mut activity = json!({
+            "@context": "https://www.w3.org/ns/activitystreams",
+            "type": "as:Create",
+            "to": "as:Public",
+            "object": {
+                "type": "https://www.w3.org/ns/activitystreams#Note",
+                "to": ["Public", "https://remote.example/users/a"],
+                "cc": [TO_PUBLIC],
+                "as:sensitive": true,
+                "toot:blurhash": "abc"
+            }
+        });
+        normalize(&mut activity);
+        assert_eq!(
+            activity,
+            json!({
+                "@context": "https://www.w3.org/ns/activitystreams",
+                "type": "Create",
+                "to": TO_PUBLIC,
+                "object": {
+                    "type": "Note",
+                    "to": [TO_PUBLIC, "https://remote.example/users/a"],
+                    "cc": [TO_PUBLIC],
+                    "sensitive": true,
+                    "blurhash": "abc"
+                }
+            })
+        );
+    }
+}
@@ -1,13 +1,27 @@
 #[macro_use]
 extern crate lazy_static;
 
-use actix_web::{web, App, HttpServer};
-
 pub mod activities;
 pub mod actors;
+pub mod api;
 pub mod app;
+pub mod authorized_fetch;
 pub mod config;
 pub mod constants;
+pub mod content;
+pub mod delivery;
+pub mod fetch;
+pub mod html;
+pub mod imaging;
+pub mod inbox;
+pub mod jsonld;
+pub mod media;
+pub mod model;
+pub mod moderation;
+pub mod nodeinfo;
 pub mod objects;
+pub mod remote_actors;
+pub mod sanitize;
+pub mod store;
 pub mod webfinger;
 pub mod http_signatures;
@@ -0,0 +1,255 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use actix_files::NamedFile;
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
+use log::{info, warn};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::app::AppState;
+use crate::config;
+use crate::constants::*;
+use crate::fetch;
+use crate::imaging::{self, ImagingError};
+use crate::objects::{ObjectDocument, ObjectImage};
+
+/// Media types accepted for upload, with the file extension each is stored
+/// and served under.
+const MEDIA_TYPES: &[(&str, &str)] = &[
+    ("image/jpeg", "jpg"),
+    ("image/png", "png"),
+    ("image/gif", "gif"),
+    ("image/webp", "webp"),
+    ("video/mp4", "mp4"),
+    ("video/webm", "webm"),
+    ("video/quicktime", "mov"),
+    ("audio/mpeg", "mp3"),
+    ("audio/ogg", "ogg"),
+    ("audio/wav", "wav"),
+    ("audio/flac", "flac"),
+];
+
+#[get("/media/{file_name}")]
+pub async fn media_service(path: web::Path<String>, req: HttpRequest) -> HttpResponse {
+    let file_name = path.into_inner();
+    let path = match media_path(&file_name) {
+        None => return HttpResponse::NotFound().finish(),
+        Some(path) => path,
+    };
+    match NamedFile::open_async(path).await {
+        Err(_err) => HttpResponse::NotFound().finish(),
+        Ok(file) => file.respond_to(&req),
+    }
+}
+
+/// Store an uploaded file under a path derived from its content and return
+/// the attachment describing it. Images are passed through the processing
+/// pipeline first, so what is stored has no EXIF metadata and comes with a
+/// thumbnail, dimensions and a blurhash.
+pub async fn store_media(
+    data: &AppState,
+    bytes: &[u8],
+    media_type: &str,
+    description: Option<&str>,
+) -> Result<ObjectDocument, MediaError> {
+    if bytes.is_empty() {
+        return Err(MediaError::Empty);
+    }
+    if bytes.len() > config::CONFIG.max_upload_size {
+        return Err(MediaError::TooLarge);
+    }
+    extension_for(media_type).ok_or(MediaError::UnsupportedType)?;
+
+    if !media_type.starts_with("image/") {
+        let url = write_media(bytes, media_type)?;
+        let document = ObjectDocument::new(OBJECT_TYPE_DOCUMENT, media_type, &url, description);
+        data.store.put_media(&media_id(&url), document.clone());
+        return Ok(document);
+    }
+
+    // Decoding and re-encoding a large image takes a while, so keep it off
+    // the executor.
+    let (owned_bytes, owned_type) = (bytes.to_vec(), media_type.to_string());
+    let processed = web::block(move || imaging::process_image(&owned_bytes, &owned_type))
+        .await
+        .map_err(|err| MediaError::Io(io::Error::other(err)))?
+        .map_err(MediaError::Image)?;
+    let url = write_media(&processed.bytes, media_type)?;
+    let thumbnail_url = write_media(&processed.thumbnail.bytes, &processed.thumbnail.media_type)?;
+
+    let mut document = ObjectDocument::new(OBJECT_TYPE_IMAGE, media_type, &url, description);
+    document.width = Some(processed.width);
+    document.height = Some(processed.height);
+    document.blurhash = processed.blurhash;
+    document.icon = Some(ObjectImage::new(
+        &processed.thumbnail.media_type,
+        &thumbnail_url,
+    ));
+    data.store.put_media(&media_id(&url), document.clone());
+    Ok(document)
+}
+
+/// Write bytes to their content-addressed location, returning their URL.
+fn write_media(bytes: &[u8], media_type: &str) -> Result<String, MediaError> {
+    let extension = extension_for(media_type).ok_or(MediaError::UnsupportedType)?;
+    let hash = format!("{:x}", Sha256::digest(bytes));
+    let file_name = format!("{}.{}", hash, extension);
+    let path = media_path(&file_name).ok_or(MediaError::UnsupportedType)?;
+    if !path.exists() {
+        fs::create_dir_all(path.parent().unwrap()).map_err(MediaError::Io)?;
+        fs::write(&path, bytes).map_err(MediaError::Io)?;
+    }
+    Ok(format!("{}/media/{}", config::CONFIG.base_url, file_name))
+}
+
+/// The id uploaded media is attached by: the file name in its URL.
+pub fn media_id(url: &str) -> String {
+    url.rsplit('/').next().unwrap_or_default().to_string()
+}
+
+/// Copy the images attached to a cached remote object into local storage,
+/// through the same pipeline as uploads, and point the object at the copies.
+pub async fn proxy_remote_media(data: AppState, object_id: String) {
+    let mut object = match data.store.get_remote_object(&object_id) {
+        None => return,
+        Some(object) => object,
+    };
+    let attachments = match object.get_mut("attachment") {
+        Some(Value::Array(attachments)) => attachments,
+        Some(attachment @ Value::Object(_)) => std::slice::from_mut(attachment),
+        _ => return,
+    };
+
+    let mut changed = false;
+    for attachment in attachments.iter_mut() {
+        let media_type = attachment["mediaType"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        let url = match attachment["url"].as_str() {
+            Some(url) if media_type.starts_with("image/") => url.to_string(),
+            _ => continue,
+        };
+        let bytes = match fetch_remote_media(&url).await {
+            Err(err) => {
+                warn!("Could not fetch remote media {}: {:?}", url, err);
+                continue;
+            }
+            Ok(bytes) => bytes,
+        };
+        match store_media(&data, &bytes, &media_type, attachment["name"].as_str()).await {
+            Err(err) => warn!("Could not process remote media {}: {:?}", url, err),
+            Ok(document) => {
+                attachment["url"] = Value::from(document.url);
+                attachment["width"] = Value::from(document.width);
+                attachment["height"] = Value::from(document.height);
+                attachment["blurhash"] = Value::from(document.blurhash);
+                changed = true;
+            }
+        }
+    }
+
+    // The object may have been deleted while we were fetching.
+    if changed && data.store.get_remote_object(&object_id).is_some() {
+        info!("Proxied remote media for {}", object_id);
+        data.store.put_remote_object(&object_id, object);
+    }
+}
+
+/// Download remote media with the same limits as object fetches, refusing
+/// hosts on private networks.
+async fn fetch_remote_media(url: &str) -> Result<Vec<u8>, MediaError> {
+    if !fetch::is_public_url(url).await {
+        return Err(MediaError::FetchFailed);
+    }
+    let mut response = fetch::CLIENT
+        .get(url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|_| MediaError::FetchFailed)?;
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|_| MediaError::FetchFailed)?
+    {
+        bytes.extend_from_slice(&chunk);
+        if bytes.len() > config::CONFIG.max_upload_size {
+            return Err(MediaError::TooLarge);
+        }
+    }
+    Ok(bytes)
+}
+
+pub fn extension_for(media_type: &str) -> Option<&'static str> {
+    MEDIA_TYPES
+        .iter()
+        .find(|(known, _)| *known == media_type)
+        .map(|(_, extension)| *extension)
+}
+
+/// Location on disk of a stored media file, sharded by the first bytes of
+/// its hash. Returns `None` for names that could not have been stored.
+pub fn media_path(file_name: &str) -> Option<PathBuf> {
+    let (hash, extension) = file_name.split_once('.')?;
+    let valid = hash.len() == 64
+        && hash
+            .chars()
+            .all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase())
+        && MEDIA_TYPES.iter().any(|(_, known)| *known == extension);
+    if !valid {
+        return None;
+    }
+    Some(
+        PathBuf::from(&config::CONFIG.media_dir)
+            .join(&hash[0..2])
+            .join(&hash[2..4])
+            .join(file_name),
+    )
+}
+
+/// An error that occured while storing an upload.
+#[derive(Debug)]
+pub enum MediaError {
+    /// The upload had no content.
+    Empty,
+    /// The upload is larger than the configured limit.
+    TooLarge,
+    /// The media type is not an image, video or audio type we accept.
+    UnsupportedType,
+    /// The upload claimed to be an image but could not be processed as one.
+    Image(ImagingError),
+    /// Remote media could not be downloaded, or is on a private network.
+    FetchFailed,
+    /// The file could not be written.
+    Io(io::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_media_path_only_accepts_stored_names() {
+        let hash = format!("{:x}", Sha256::digest(b"hello"));
+        let path = media_path(&format!("{}.png", hash)).expect("a valid name to map to a path");
+        assert!(path.ends_with(format!("{}/{}/{}.png", &hash[0..2], &hash[2..4], hash)));
+
+        assert_eq!(media_path(&format!("{}.exe", hash)), None);
+        assert_eq!(media_path(&format!("{}.png", hash.to_uppercase())), None);
+        assert_eq!(media_path("../../etc/passwd.png"), None);
+    }
+
+    #[actix_web::test]
+    async fn test_remote_media_on_private_hosts_is_refused() {
+        for url in ["http://127.0.0.1:1/a.png", "http://localhost/a.png"] {
+            assert!(matches!(
+                fetch_remote_media(url).await,
+                Err(MediaError::FetchFailed)
+            ));
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A property that may hold a single value or an array of them. Whichever
+/// shape was received is kept, so documents serialize back as they came.
+///
+/// `Many` comes first because untagged variants are tried in order, and a `T`
+/// such as `Value` would otherwise take the whole array as one value.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum OneOrMany<T> {
+    Many(Vec<T>),
+    One(T),
+}
+
+impl<T> OneOrMany<T> {
+    pub fn iter(&self) -> std::slice::Iter<'_, T> {
+        match self {
+            OneOrMany::One(value) => std::slice::from_ref(value).iter(),
+            OneOrMany::Many(values) => values.iter(),
+        }
+    }
+
+    pub fn first(&self) -> Option<&T> {
+        self.iter().next()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        matches!(self, OneOrMany::Many(values) if values.is_empty())
+    }
+
+    pub fn push(&mut self, value: T) {
+        match self {
+            OneOrMany::Many(values) => values.push(value),
+            OneOrMany::One(_) => {
+                if let OneOrMany::One(first) = std::mem::take(self) {
+                    *self = OneOrMany::Many(vec![first, value]);
+                }
+            }
+        }
+    }
+
+    pub fn into_vec(self) -> Vec<T> {
+        match self {
+            OneOrMany::One(value) => vec![value],
+            OneOrMany::Many(values) => values,
+        }
+    }
+}
+
+impl<T: PartialEq> OneOrMany<T> {
+    pub fn contains(&self, value: &T) -> bool {
+        self.iter().any(|candidate| candidate == value)
+    }
+}
+
+impl<T> Default for OneOrMany<T> {
+    fn default() -> Self {
+        OneOrMany::Many(vec![])
+    }
+}
+
+impl<T> From<T> for OneOrMany<T> {
+    fn from(value: T) -> Self {
+        OneOrMany::One(value)
+    }
+}
+
+impl<T> From<Vec<T>> for OneOrMany<T> {
+    fn from(values: Vec<T>) -> Self {
+        OneOrMany::Many(values)
+    }
+}
+
+/// A JSON-LD `@context`: an IRI, a map of term definitions, or an array
+/// mixing both.
+pub type Context = OneOrMany<Value>;
+
+/// A reference to another object, either by IRI or embedded in full.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum Reference<T> {
+    Iri(String),
+    Object(Box<T>),
+}
+
+impl<T: Identified> Reference<T> {
+    /// The id of the referenced object, however it was given.
+    pub fn id(&self) -> Option<&str> {
+        match self {
+            Reference::Iri(id) => Some(id),
+            Reference::Object(object) => object.id(),
+        }
+    }
+}
+
+impl<T> Reference<T> {
+    /// The embedded object, if it was given in full.
+    pub fn object(&self) -> Option<&T> {
+        match self {
+            Reference::Iri(_) => None,
+            Reference::Object(object) => Some(object),
+        }
+    }
+}
+
+impl<T> From<&str> for Reference<T> {
+    fn from(id: &str) -> Self {
+        Reference::Iri(id.to_string())
+    }
+}
+
+/// Objects that may carry an id.
+pub trait Identified {
+    fn id(&self) -> Option<&str>;
+}
+
+impl Identified for Value {
+    fn id(&self) -> Option<&str> {
+        self["id"].as_str()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::activities::Activity;
+    use serde_json::json;
+
+    #[test]
+    fn test_one_or_many_keeps_its_shape() {
+        let one: OneOrMany<String> = serde_json::from_value(json!("a")).unwrap();
+        let many: OneOrMany<String> = serde_json::from_value(json!(["a", "b"])).unwrap();
+        assert_eq!(one.iter().collect::<Vec<_>>(), vec!["a"]);
+        assert!(many.contains(&"b".to_string()));
+        assert_eq!(serde_json::to_value(&one).unwrap(), json!("a"));
+        assert_eq!(serde_json::to_value(&many).unwrap(), json!(["a", "b"]));
+
+        // Values and references could hold the whole array; they must not.
+        let context: Context = serde_json::from_value(json!(["a", {"b": "c"}])).unwrap();
+        assert_eq!(context.iter().count(), 2);
+        let references: OneOrMany<Reference<Value>> =
+            serde_json::from_value(json!(["https://a.example/1", {"id": "https://b.example/2"}]))
+                .unwrap();
+        let ids: Vec<_> = references.iter().filter_map(Reference::id).collect();
+        assert_eq!(ids, ["https://a.example/1", "https://b.example/2"]);
+    }
+
+    #[test]
+    fn test_inbound_activity_round_trips_unknown_properties() {
+        let document = json!({
+            "@context": [
+                "https://www.w3.org/ns/activitystreams",
+                {"toot": "http://joinmastodon.org/ns#"}
+            ],
+            "id": "https://remote.example/activities/1",
+            "type": "Create",
+            "actor": {"id": "https://remote.example/users/a", "type": "Person"},
+            "object": {"id": "https://remote.example/notes/1", "type": "Note"},
+            "to": "https://www.w3.org/ns/activitystreams#Public",
+            "cc": ["https://remote.example/users/a/followers"],
+            "signature": {"type": "RsaSignature2017"}
+        });
+
+        let activity: Activity<Reference<Value>> =
+            serde_json::from_value(document.clone()).expect("activity to parse");
+        assert_eq!(activity.actor.id(), Some("https://remote.example/users/a"));
+        assert_eq!(activity.object.id(), Some("https://remote.example/notes/1"));
+        assert_eq!(activity.cc.iter().count(), 1);
+        assert!(activity.unknown.contains_key("signature"));
+        assert_eq!(serde_json::to_value(&activity).unwrap(), document);
+    }
+}
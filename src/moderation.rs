@@ -0,0 +1,351 @@
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::config;
+use crate::store::Store;
+
+/// Columns of a Mastodon domain block export, in order.
+const CSV_COLUMNS: &[&str] = &[
+    "#domain",
+    "#severity",
+    "#reject_media",
+    "#reject_reports",
+    "#public_comment",
+    "#obfuscate",
+];
+
+/// How content from a domain is treated.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Severity {
+    /// Federate as usual; only the other settings of the policy apply.
+    #[serde(rename = "noop")]
+    Noop,
+    /// Accept activities, but keep them out of public views.
+    #[serde(rename = "silence")]
+    Silence,
+    /// Drop every activity and refuse every fetch. Mastodon calls this
+    /// suspending the domain.
+    #[serde(rename = "suspend", alias = "reject")]
+    Reject,
+}
+
+impl Severity {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim() {
+            "noop" => Some(Severity::Noop),
+            "silence" => Some(Severity::Silence),
+            "suspend" | "reject" => Some(Severity::Reject),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Noop => "noop",
+            Severity::Silence => "silence",
+            Severity::Reject => "suspend",
+        }
+    }
+}
+
+/// Moderation policy for a remote domain and its subdomains.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DomainPolicy {
+    pub domain: String,
+    pub severity: Severity,
+    /// Don't proxy or show media attached to the domain's posts.
+    #[serde(default)]
+    pub reject_media: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_comment: Option<String>,
+}
+
+/// Lowercase a domain and check it is a bare host name.
+pub fn normalize_domain(domain: &str) -> Option<String> {
+    let domain = domain.trim().trim_end_matches('.').to_lowercase();
+    let valid = !domain.is_empty()
+        && domain
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.' || c == ':');
+    valid.then_some(domain)
+}
+
+/// The lowercased host of a URL.
+fn host_of(url: &str) -> Option<String> {
+    Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_lowercase))
+}
+
+/// Whether everything from the server at `url` must be dropped: its domain
+/// is rejected or, in allowlist mode, not allowed.
+pub fn rejects(store: &Store, url: &str) -> bool {
+    let host = match host_of(url) {
+        Some(host) => host,
+        None => return false,
+    };
+    if config::CONFIG.hosted_domain(&host).is_some() {
+        return false;
+    }
+    if config::CONFIG.allowlist_mode && !store.is_allowed_domain(&host) {
+        return true;
+    }
+    store
+        .domain_policy(&host)
+        .is_some_and(|policy| policy.severity == Severity::Reject)
+}
+
+/// Whether content from the server at `url` is kept out of public views.
+pub fn silences(store: &Store, url: &str) -> bool {
+    rejects(store, url)
+        || host_of(url)
+            .and_then(|host| store.domain_policy(&host))
+            .is_some_and(|policy| policy.severity == Severity::Silence)
+}
+
+/// Whether media from the server at `url` is neither proxied nor shown.
+pub fn rejects_media(store: &Store, url: &str) -> bool {
+    rejects(store, url)
+        || host_of(url)
+            .and_then(|host| store.domain_policy(&host))
+            .is_some_and(|policy| policy.reject_media)
+}
+
+/// Export policies as CSV in the format Mastodon uses for domain blocks.
+pub fn to_csv(policies: &[DomainPolicy]) -> String {
+    let mut csv = CSV_COLUMNS.join(",");
+    csv.push('\n');
+    for policy in policies {
+        let row = [
+            policy.domain.as_str(),
+            policy.severity.as_str(),
+            if policy.reject_media { "true" } else { "false" },
+            "false",
+            policy.public_comment.as_deref().unwrap_or_default(),
+            "false",
+        ];
+        let row: Vec<String> = row.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Import policies from a Mastodon domain block CSV. Columns are found by
+/// their header, so older exports with fewer columns work too; a file without
+/// a header is read as the current column order. Reports and obfuscation
+/// don't apply here and are ignored.
+pub fn from_csv(csv: &str) -> Result<Vec<DomainPolicy>, ImportError> {
+    let mut rows = parse_csv(csv).into_iter().enumerate().peekable();
+    let header: Vec<String> = match rows.peek() {
+        Some((_, first)) if first.first().is_some_and(|cell| cell.starts_with('#')) => {
+            let header = first.iter().map(|cell| cell.trim().to_string()).collect();
+            rows.next();
+            header
+        }
+        _ => CSV_COLUMNS
+            .iter()
+            .map(|column| column.to_string())
+            .collect(),
+    };
+    let column = |name: &str| header.iter().position(|column| column == name);
+    let domain_column = column("#domain").ok_or(ImportError::MissingDomainColumn)?;
+    let (severity_column, media_column, comment_column) = (
+        column("#severity"),
+        column("#reject_media"),
+        column("#public_comment"),
+    );
+
+    let mut policies = Vec::new();
+    for (index, row) in rows {
+        let cell = |column: Option<usize>| {
+            column
+                .and_then(|column| row.get(column))
+                .map(|cell| cell.trim())
+                .filter(|cell| !cell.is_empty())
+        };
+        let invalid = || ImportError::InvalidRow(index + 1);
+        let domain = cell(Some(domain_column))
+            .and_then(normalize_domain)
+            .ok_or_else(invalid)?;
+        let severity = match cell(severity_column) {
+            None => Severity::Reject,
+            Some(severity) => Severity::parse(severity).ok_or_else(invalid)?,
+        };
+        policies.push(DomainPolicy {
+            domain,
+            severity,
+            reject_media: cell(media_column) == Some("true"),
+            public_comment: cell(comment_column).map(str::to_string),
+        });
+    }
+    Ok(policies)
+}
+
+/// Apply the policies configured at startup: those in a Mastodon domain
+/// block CSV, then `blocked` domains as rejections, overriding the CSV, and
+/// `allowed` domains onto the allowlist.
+pub fn seed(
+    store: &Store,
+    csv: Option<&str>,
+    blocked: &[String],
+    allowed: &[String],
+) -> Result<(), ImportError> {
+    for policy in csv.map(from_csv).transpose()?.unwrap_or_default() {
+        store.put_domain_policy(policy);
+    }
+    for domain in blocked {
+        let domain = normalize_domain(domain).ok_or(ImportError::InvalidDomain)?;
+        store.put_domain_policy(DomainPolicy {
+            domain,
+            severity: Severity::Reject,
+            reject_media: true,
+            public_comment: None,
+        });
+    }
+    for domain in allowed {
+        let domain = normalize_domain(domain).ok_or(ImportError::InvalidDomain)?;
+        store.allow_domain(&domain);
+    }
+    Ok(())
+}
+
+/// Split CSV into rows of fields, honouring quoted fields. Blank lines are
+/// skipped.
+fn parse_csv(csv: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = csv.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            ('"', true) => quoted = false,
+            ('"', false) if field.is_empty() => quoted = true,
+            (',', false) => row.push(std::mem::take(&mut field)),
+            ('\n', false) => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            ('\r', false) => (),
+            (c, _) => field.push(c),
+        }
+    }
+    row.push(field);
+    rows.push(row);
+    rows.retain(|row| row.iter().any(|field| !field.trim().is_empty()));
+    rows
+}
+
+/// An error that occured while importing domain policies.
+#[derive(Debug, PartialEq)]
+pub enum ImportError {
+    /// The header has no `#domain` column.
+    MissingDomainColumn,
+    /// The row with this number, counting from 1 and including any header,
+    /// has a bad domain or severity.
+    InvalidRow(usize),
+    /// A domain given in the configuration is not a bare host name.
+    InvalidDomain,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_round_trip() {
+        let policies = vec![
+            DomainPolicy {
+                domain: "spam.example".to_string(),
+                severity: Severity::Reject,
+                reject_media: true,
+                public_comment: Some("Spam, \"mostly\"".to_string()),
+            },
+            DomainPolicy {
+                domain: "loud.example".to_string(),
+                severity: Severity::Silence,
+                reject_media: false,
+                public_comment: None,
+            },
+        ];
+        let csv = to_csv(&policies);
+        assert!(csv.starts_with("#domain,#severity,#reject_media,"));
+        assert!(csv.contains("spam.example,suspend,true,false,\"Spam, \"\"mostly\"\"\",false"));
+        assert_eq!(from_csv(&csv), Ok(policies));
+    }
+
+    #[test]
+    fn test_from_csv_variants() {
+        let csv = "#domain,#severity\r\nA.Example,silence\r\n\r\nb.example,\r\n";
+        let policies = from_csv(csv).unwrap();
+        assert_eq!(policies[0].domain, "a.example");
+        assert_eq!(policies[0].severity, Severity::Silence);
+        assert_eq!(policies[1].severity, Severity::Reject);
+
+        assert_eq!(
+            from_csv("c.example,silence,false\nd.example,banish,false"),
+            Err(ImportError::InvalidRow(2))
+        );
+        assert_eq!(
+            from_csv("#severity\nsuspend"),
+            Err(ImportError::MissingDomainColumn)
+        );
+    }
+
+    #[test]
+    fn test_policy_covers_subdomains() {
+        let store = Store::new();
+        store.put_domain_policy(DomainPolicy {
+            domain: "example.net".to_string(),
+            severity: Severity::Reject,
+            reject_media: false,
+            public_comment: None,
+        });
+        store.put_domain_policy(DomainPolicy {
+            domain: "ok.example.net".to_string(),
+            severity: Severity::Silence,
+            reject_media: true,
+            public_comment: None,
+        });
+        assert!(rejects(&store, "https://social.example.net/users/a"));
+        assert!(!rejects(&store, "https://ok.example.net/users/a"));
+        assert!(silences(&store, "https://ok.example.net/users/a"));
+        assert!(rejects_media(&store, "https://ok.example.net/users/a"));
+        assert!(!rejects(&store, "https://notexample.net/users/a"));
+        assert!(!silences(&store, "https://other.example/users/a"));
+    }
+
+    #[test]
+    fn test_seed_from_config() {
+        let store = Store::new();
+        let csv = "#domain,#severity\nbad.example,silence\nworse.example,suspend\n";
+        seed(
+            &store,
+            Some(csv),
+            &["bad.example".to_string()],
+            &["good.example".to_string()],
+        )
+        .unwrap();
+        assert!(rejects(&store, "https://bad.example/users/a"));
+        assert!(rejects(&store, "https://worse.example/users/a"));
+        assert!(store.is_allowed_domain("good.example"));
+
+        assert_eq!(
+            seed(&store, None, &["not a domain".to_string()], &[]),
+            Err(ImportError::InvalidDomain)
+        );
+    }
+}
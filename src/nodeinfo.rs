@@ -0,0 +1,217 @@
+use actix_web::http::header;
+use actix_web::{get, web, HttpResponse, Responder};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::app::AppState;
+use crate::config;
+use crate::store::Store;
+
+/// NodeInfo schema versions we serve, newest last.
+const VERSIONS: &[&str] = &["2.0", "2.1"];
+
+fn schema(version: &str) -> String {
+    format!("http://nodeinfo.diaspora.software/ns/schema/{}", version)
+}
+
+/// Discovery document linking to each NodeInfo version.
+#[get("/.well-known/nodeinfo")]
+pub async fn discovery_service() -> impl Responder {
+    let links: Vec<_> = VERSIONS
+        .iter()
+        .map(|version| {
+            json!({
+                "rel": schema(version),
+                "href": format!("{}/nodeinfo/{}", config::CONFIG.base_url, version),
+            })
+        })
+        .collect();
+    HttpResponse::Ok()
+        .insert_header((header::ACCESS_CONTROL_ALLOW_ORIGIN, "*"))
+        .json(json!({ "links": links }))
+}
+
+#[get("/nodeinfo/{version:2\\.0|2\\.1}")]
+pub async fn nodeinfo_service(
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let version = path.into_inner();
+    let document = NodeInfo::new(&version, usage(&data.store, Utc::now()));
+    HttpResponse::Ok()
+        .insert_header((
+            header::CONTENT_TYPE,
+            format!("application/json; profile=\"{}#\"", schema(&version)),
+        ))
+        .insert_header((header::ACCESS_CONTROL_ALLOW_ORIGIN, "*"))
+        .body(serde_json::to_string_pretty(&document).unwrap())
+}
+
+/// Count users and posts. Users are active over a period if they published
+/// anything in it.
+pub fn usage(store: &Store, now: DateTime<Utc>) -> NodeInfoUsage {
+    let actors = store.active_actors();
+    let active_since = |since: DateTime<Utc>| {
+        actors
+            .iter()
+            .filter(|name| store.last_published(name).is_some_and(|last| last > since))
+            .count()
+    };
+    NodeInfoUsage {
+        users: NodeInfoUsers {
+            total: actors.len(),
+            active_month: active_since(now - Duration::days(30)),
+            active_halfyear: active_since(now - Duration::days(180)),
+        },
+        local_posts: store.local_post_count(),
+    }
+}
+
+/// A NodeInfo document, in the shape shared by versions 2.0 and 2.1.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeInfo {
+    pub version: String,
+    pub software: NodeInfoSoftware,
+    pub protocols: Vec<String>,
+    pub services: NodeInfoServices,
+    pub open_registrations: bool,
+    pub usage: NodeInfoUsage,
+    pub metadata: Map<String, Value>,
+}
+
+impl NodeInfo {
+    pub fn new(version: &str, usage: NodeInfoUsage) -> Self {
+        // Repository and homepage were only added in 2.1.
+        let (repository, homepage) = match version {
+            "2.0" => (None, None),
+            _ => (
+                non_empty(env!("CARGO_PKG_REPOSITORY")),
+                non_empty(env!("CARGO_PKG_HOMEPAGE")),
+            ),
+        };
+        let mut metadata = Map::new();
+        metadata.insert(
+            "nodeName".to_string(),
+            Value::from(config::CONFIG.domain.clone()),
+        );
+        NodeInfo {
+            version: version.to_string(),
+            software: NodeInfoSoftware {
+                name: env!("CARGO_PKG_NAME").to_string(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                repository,
+                homepage,
+            },
+            protocols: vec!["activitypub".to_string()],
+            services: NodeInfoServices::default(),
+            open_registrations: config::CONFIG.open_registrations,
+            usage,
+            metadata,
+        }
+    }
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    match value {
+        "" => None,
+        value => Some(value.to_string()),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct NodeInfoSoftware {
+    pub name: String,
+    pub version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repository: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub homepage: Option<String>,
+}
+
+/// Third party services we bridge to, of which there are none.
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct NodeInfoServices {
+    pub inbound: Vec<String>,
+    pub outbound: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeInfoUsage {
+    pub users: NodeInfoUsers,
+    pub local_posts: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeInfoUsers {
+    pub total: usize,
+    pub active_month: usize,
+    pub active_halfyear: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::*;
+    use crate::objects::ObjectNote;
+
+    #[test]
+    fn test_usage_counts_recently_active_users() {
+        let store = Store::new();
+        let now = Utc::now();
+        for name in ["a", "b", "c"] {
+            store
+                .register_actor(name, ACTOR_TYPE_APPLICATION, "example.com")
+                .unwrap();
+        }
+        store.push_outbox(
+            "a",
+            json!({"published": (now - Duration::days(2)).to_rfc3339()}),
+        );
+        store.push_outbox(
+            "b",
+            json!({"published": (now - Duration::days(90)).to_rfc3339()}),
+        );
+        store.put_note(ObjectNote::new(
+            "https://example.com/@a/notes/1",
+            "a",
+            None,
+            "hi",
+        ));
+
+        assert_eq!(
+            usage(&store, now),
+            NodeInfoUsage {
+                users: NodeInfoUsers {
+                    total: 3,
+                    active_month: 1,
+                    active_halfyear: 2,
+                },
+                local_posts: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_version_2_0_has_no_repository() {
+        let usage = || NodeInfoUsage {
+            users: NodeInfoUsers {
+                total: 0,
+                active_month: 0,
+                active_halfyear: 0,
+            },
+            local_posts: 0,
+        };
+        let document = serde_json::to_value(NodeInfo::new("2.0", usage())).unwrap();
+        assert_eq!(document["software"]["name"], "rust-activitypub-play");
+        assert_eq!(document["protocols"], json!(["activitypub"]));
+        assert!(document["software"].get("repository").is_none());
+        assert_eq!(
+            NodeInfo::new("2.1", usage()).software.version,
+            env!("CARGO_PKG_VERSION")
+        );
+    }
+}
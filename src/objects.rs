@@ -1,44 +1,396 @@
-use actix_web::{get, web, HttpResponse, Responder};
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
 use chrono::prelude::*;
 use serde::{Deserialize, Serialize};
 use serde_json;
+use serde_json::{Map, Value};
+use std::collections::HashSet;
 
+use crate::actors;
 use crate::app::AppState;
-// use crate::config;
+use crate::authorized_fetch;
 use crate::constants::*;
+use crate::html;
+use crate::jsonld;
+use crate::model::{Identified, OneOrMany};
+use crate::moderation;
+use crate::store::{Interaction, StoredObject};
+
+/// Most options a poll may offer.
+pub const MAX_POLL_OPTIONS: usize = 4;
+
+/// Longest a poll may stay open, in seconds: about a month.
+pub const MAX_POLL_DURATION: i64 = 30 * 24 * 60 * 60;
 
 #[derive(Deserialize)]
 pub struct NotesServicePathInfo {
     actor_name: String,
+    kind: ObjectKind,
     note_id: String,
 }
 
-#[get("/@{actor_name}/notes/{note_id}.json")]
+/// The kinds of object local actors publish, each served from its own
+/// collection of routes.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+pub enum ObjectKind {
+    #[default]
+    #[serde(rename = "notes")]
+    Note,
+    #[serde(rename = "articles")]
+    Article,
+    #[serde(rename = "pages")]
+    Page,
+}
+
+impl ObjectKind {
+    pub const ALL: [ObjectKind; 3] = [ObjectKind::Note, ObjectKind::Article, ObjectKind::Page];
+
+    /// Path segment the kind's routes live under.
+    pub fn collection(&self) -> &'static str {
+        match self {
+            ObjectKind::Note => "notes",
+            ObjectKind::Article => "articles",
+            ObjectKind::Page => "pages",
+        }
+    }
+
+    pub fn object_type(&self) -> &'static str {
+        match self {
+            ObjectKind::Note => OBJECT_TYPE_NOTE,
+            ObjectKind::Article => OBJECT_TYPE_ARTICLE,
+            ObjectKind::Page => OBJECT_TYPE_PAGE,
+        }
+    }
+
+    pub fn from_object_type(object_type: &str) -> Option<Self> {
+        ObjectKind::ALL
+            .into_iter()
+            .find(|kind| kind.object_type() == object_type)
+    }
+}
+
+#[get("/@{actor_name}/{kind:notes|articles|pages}/{note_id}.json")]
 pub async fn notes_service(
     path: web::Path<NotesServicePathInfo>,
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if let Err(err) = authorized_fetch::authorize(&data, &req).await {
+        return err.response();
+    }
+    let actor = match actors::actor_lookup(&data, &path.actor_name) {
+        Err(actors::ResolverError::NotFound) => return HttpResponse::NotFound().finish(),
+        Err(actors::ResolverError::Gone(_)) => return HttpResponse::Gone().finish(),
+        Ok(actor) => actor,
+    };
+
+    match note_lookup(&data, &actor, path.kind, &path.note_id) {
+        Err(LookupError::NotFound) => HttpResponse::NotFound().finish(),
+        Err(LookupError::Gone(tombstone)) => {
+            jsonld::respond(HttpResponse::Gone(), MEDIA_TYPE_ACTIVITY_JSON, &tombstone)
+        }
+        Ok(result) => jsonld::respond(HttpResponse::Ok(), MEDIA_TYPE_ACTIVITY_JSON, &result),
+    }
+}
+
+#[get("/@{actor_name}/{kind:notes|articles|pages}/{note_id}/replies.json")]
+pub async fn replies_service(
+    path: web::Path<NotesServicePathInfo>,
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if let Err(err) = authorized_fetch::authorize(&data, &req).await {
+        return err.response();
+    }
+    let actor = match actors::actor_lookup(&data, &path.actor_name) {
+        Err(actors::ResolverError::NotFound) => return HttpResponse::NotFound().finish(),
+        Err(actors::ResolverError::Gone(_)) => return HttpResponse::Gone().finish(),
+        Ok(actor) => actor,
+    };
+
+    match note_lookup(&data, &actor, path.kind, &path.note_id) {
+        Err(LookupError::NotFound) => HttpResponse::NotFound().finish(),
+        Err(LookupError::Gone(_)) => HttpResponse::Gone().finish(),
+        Ok(result) => jsonld::respond(
+            HttpResponse::Ok(),
+            MEDIA_TYPE_ACTIVITY_JSON,
+            &result.replies,
+        ),
+    }
+}
+
+#[get("/@{actor_name}/{kind:notes|articles|pages}/{note_id}/likes.json")]
+pub async fn likes_service(
+    path: web::Path<NotesServicePathInfo>,
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    interactions_response(&data, &req, &path, Interaction::Like).await
+}
+
+#[get("/@{actor_name}/{kind:notes|articles|pages}/{note_id}/shares.json")]
+pub async fn shares_service(
+    path: web::Path<NotesServicePathInfo>,
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    interactions_response(&data, &req, &path, Interaction::Announce).await
+}
+
+/// The collection of actors who liked or announced a local object.
+async fn interactions_response(
+    data: &AppState,
+    req: &HttpRequest,
+    path: &NotesServicePathInfo,
+    interaction: Interaction,
+) -> HttpResponse {
+    if let Err(err) = authorized_fetch::authorize(data, req).await {
+        return err.response();
+    }
+    let actor = match actors::actor_lookup(data, &path.actor_name) {
+        Err(actors::ResolverError::NotFound) => return HttpResponse::NotFound().finish(),
+        Err(actors::ResolverError::Gone(_)) => return HttpResponse::Gone().finish(),
+        Ok(actor) => actor,
+    };
+
+    match note_lookup(data, &actor, path.kind, &path.note_id) {
+        Err(LookupError::NotFound) => HttpResponse::NotFound().finish(),
+        Err(LookupError::Gone(_)) => HttpResponse::Gone().finish(),
+        Ok(note) => {
+            let collection = ObjectCollection::new(
+                &actor.interactions_url(path.kind, &path.note_id, interaction),
+                data.store.interactions(interaction, &note.id),
+            );
+            jsonld::respond(HttpResponse::Ok(), MEDIA_TYPE_ACTIVITY_JSON, &collection)
+        }
+    }
+}
+
+/// The Create and Announce activities an actor has published, newest first.
+#[get("/@{actor_name}/outbox")]
+pub async fn outbox_service(
+    path: web::Path<String>,
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if let Err(err) = authorized_fetch::authorize(&data, &req).await {
+        return err.response();
+    }
+    let actor = match actors::actor_lookup(&data, &path.into_inner()) {
+        Err(actors::ResolverError::NotFound) => return HttpResponse::NotFound().finish(),
+        Err(actors::ResolverError::Gone(_)) => return HttpResponse::Gone().finish(),
+        Ok(actor) => actor,
+    };
+
+    let outbox = ObjectOrderedCollection::new(&actor.outbox_url(), data.store.outbox(&actor.name));
+    jsonld::respond(HttpResponse::Ok(), MEDIA_TYPE_ACTIVITY_JSON, &outbox)
+}
+
+/// A local object's id: its ActivityPub representation when asked for one
+/// through `Accept`, and its HTML page otherwise.
+#[get("/@{actor_name}/{kind:notes|articles|pages}/{note_id}")]
+pub async fn object_page_service(
+    path: web::Path<NotesServicePathInfo>,
+    req: HttpRequest,
     data: web::Data<AppState>,
 ) -> impl Responder {
-    match note_lookup(&path.actor_name, &path.note_id) {
-        Err(_err) => HttpResponse::NotFound().finish(),
-        Ok(result) => HttpResponse::Ok().body(serde_json::to_string_pretty(&result).unwrap()),
+    let actor = match actors::actor_lookup(&data, &path.actor_name) {
+        Err(actors::ResolverError::NotFound) => return HttpResponse::NotFound().finish(),
+        Err(actors::ResolverError::Gone(_)) => return HttpResponse::Gone().finish(),
+        Ok(actor) => actor,
+    };
+
+    let media_type = jsonld::negotiate(&req);
+    if media_type.is_some() {
+        if let Err(err) = authorized_fetch::authorize(&data, &req).await {
+            return err.response();
+        }
+    }
+    let mut response = match (
+        note_lookup(&data, &actor, path.kind, &path.note_id),
+        media_type,
+    ) {
+        (Err(LookupError::NotFound), _) => HttpResponse::NotFound().finish(),
+        (Err(LookupError::Gone(tombstone)), Some(media_type)) => {
+            jsonld::respond(HttpResponse::Gone(), media_type, &tombstone)
+        }
+        (Err(LookupError::Gone(_)), None) => HttpResponse::Gone()
+            .content_type(MEDIA_TYPE_HTML)
+            .body(html::gone_page()),
+        (Ok(object), Some(media_type)) => jsonld::respond(HttpResponse::Ok(), media_type, &object),
+        // Followers-only and direct posts have no public page.
+        (Ok(object), None) if !object.is_public() => HttpResponse::NotFound().finish(),
+        (Ok(object), None) => {
+            let parent = object
+                .in_reply_to
+                .as_deref()
+                .and_then(|parent| object_value(&data, parent));
+            HttpResponse::Ok()
+                .content_type(MEDIA_TYPE_HTML)
+                .body(html::object_page(&actor, &object, parent.as_ref()))
+        }
+    };
+    response
+        .headers_mut()
+        .insert(header::VARY, HeaderValue::from_static("Accept"));
+    response
+}
+
+/// Look up a local object of the given kind, filling in the replies, likes
+/// and shares we currently know of.
+pub fn note_lookup(
+    data: &AppState,
+    actor: &actors::LocalActorPerson,
+    kind: ObjectKind,
+    note_id: &str,
+) -> Result<ObjectNote, LookupError> {
+    match data.store.get_object(&actor.object_url(kind, note_id)) {
+        None => Err(LookupError::NotFound),
+        Some(StoredObject::Tombstone(tombstone)) => Err(LookupError::Gone(tombstone)),
+        Some(StoredObject::Note(mut note)) => {
+            note.replies = Some(ObjectCollection::new(
+                &actor.replies_url(kind, note_id),
+                data.store.replies(&note.id),
+            ));
+            let count_only = |interaction| {
+                Some(ObjectCollection::count_only(
+                    &actor.interactions_url(kind, note_id, interaction),
+                    data.store.interactions(interaction, &note.id).len(),
+                ))
+            };
+            note.likes = count_only(Interaction::Like);
+            note.shares = count_only(Interaction::Announce);
+            tally_poll(data, &mut note);
+            Ok(*note)
+        }
+    }
+}
+
+/// Look up a local object by id, whatever its kind.
+pub fn any_lookup(
+    data: &AppState,
+    actor: &actors::LocalActorPerson,
+    note_id: &str,
+) -> Result<ObjectNote, LookupError> {
+    for kind in ObjectKind::ALL {
+        match note_lookup(data, actor, kind, note_id) {
+            Err(LookupError::NotFound) => continue,
+            result => return result,
+        }
+    }
+    Err(LookupError::NotFound)
+}
+
+/// Look up a local object by its full id.
+pub fn id_lookup(
+    data: &AppState,
+    actor: &actors::LocalActorPerson,
+    id: &str,
+) -> Result<ObjectNote, LookupError> {
+    for kind in ObjectKind::ALL {
+        if let Some(note_id) = id.strip_prefix(&actor.object_url(kind, "")) {
+            return note_lookup(data, actor, kind, note_id);
+        }
+    }
+    Err(LookupError::NotFound)
+}
+
+/// An actor's public posts, newest first.
+pub fn public_posts(data: &AppState, actor: &actors::LocalActorPerson) -> Vec<ObjectNote> {
+    data.store
+        .outbox(&actor.name)
+        .iter()
+        .filter(|activity| activity["type"] == ACTIVITY_TYPE_CREATE)
+        .filter_map(|activity| activity["object"]["id"].as_str())
+        .filter_map(|id| id_lookup(data, actor, id).ok())
+        .filter(ObjectNote::is_public)
+        .collect()
+}
+
+/// Fill in how many votes each option of a poll has, and how many people
+/// voted. Does nothing for notes that are not polls.
+pub fn tally_poll(data: &AppState, note: &mut ObjectNote) {
+    if note.object_type != OBJECT_TYPE_QUESTION {
+        return;
+    }
+    let votes = data.store.votes(&note.id);
+    for option in note.one_of.iter_mut().chain(note.any_of.iter_mut()) {
+        option.replies.total_items = votes
+            .values()
+            .filter(|choices| choices.contains(&option.name))
+            .count();
+    }
+    note.voters_count = Some(votes.len());
+}
+
+/// Any object we know by id, local or cached from another server.
+pub fn object_value(data: &AppState, id: &str) -> Option<Value> {
+    match data.store.get_object(id) {
+        Some(StoredObject::Note(note)) => serde_json::to_value(note).ok(),
+        Some(StoredObject::Tombstone(_)) => None,
+        None => data.store.get_remote_object(id),
+    }
+}
+
+/// A note together with every known reply to it, recursively.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct ThreadNode {
+    pub object: Value,
+    pub replies: Vec<ThreadNode>,
+}
+
+/// Build the whole thread containing `id`, starting from the furthest
+/// public ancestor we know of. Threads are shown to anyone, so only public
+/// objects are part of them.
+pub fn thread_tree(data: &AppState, id: &str) -> Option<ThreadNode> {
+    let mut root = public_object_value(data, id)?;
+    let mut seen = HashSet::from([id.to_string()]);
+    while let Some(parent_id) = root["inReplyTo"].as_str().map(str::to_string) {
+        if !seen.insert(parent_id.clone()) {
+            break;
+        }
+        match public_object_value(data, &parent_id) {
+            Some(parent) => root = parent,
+            None => break,
+        }
     }
+
+    let mut seen = HashSet::new();
+    Some(thread_node(data, root, &mut seen))
+}
+
+fn thread_node(data: &AppState, object: Value, seen: &mut HashSet<String>) -> ThreadNode {
+    let id = object["id"].as_str().unwrap_or_default().to_string();
+    seen.insert(id.clone());
+    let reply_ids: Vec<String> = data
+        .store
+        .replies(&id)
+        .into_iter()
+        .filter(|reply_id| !seen.contains(reply_id))
+        // Threads are public, so silenced domains are left out.
+        .filter(|reply_id| !moderation::silences(&data.store, reply_id))
+        .collect();
+    let replies = reply_ids
+        .iter()
+        .filter_map(|reply_id| public_object_value(data, reply_id))
+        .map(|reply| thread_node(data, reply, seen))
+        .collect();
+    ThreadNode { object, replies }
 }
 
-pub fn note_lookup(actor_name: &String, note_id: &String) -> Result<ObjectNote, LookupError> {
-    Ok(ObjectNote::new(
-        &"bar".to_string(),
-        &"bar".to_string(),
-        &"bar".to_string(),
-        &"bar".to_string(),
-    ))
+fn public_object_value(data: &AppState, id: &str) -> Option<Value> {
+    object_value(data, id).filter(jsonld::is_addressed_to_public)
 }
 
 #[derive(Debug, PartialEq)]
 pub enum LookupError {
-    // NotFound,
+    /// No object was ever stored under the requested id.
+    NotFound,
+    /// The object existed but has since been deleted.
+    Gone(ObjectTombstone),
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct ObjectNote {
     pub id: String,
@@ -46,26 +398,606 @@ pub struct ObjectNote {
     pub object_type: String,
     pub published: DateTime<Utc>,
     pub attributed_to: String,
-    pub in_reply_to: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub in_reply_to: Option<String>,
+    /// Title of an article or page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    /// Whether the content or attachments should be hidden behind the
+    /// summary until the reader chooses to see them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sensitive: Option<bool>,
     pub content: String,
-    pub to: String,
+    /// Where an HTML rendering of the object can be read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    pub to: OneOrMany<String>,
+    #[serde(default, skip_serializing_if = "OneOrMany::is_empty")]
+    pub cc: OneOrMany<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tag: Vec<ObjectTag>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachment: Vec<ObjectDocument>,
+    /// Identifies the thread this note belongs to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context: Option<String>,
+    /// Same as `context`, under the name Mastodon uses.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conversation: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replies: Option<ObjectCollection>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub likes: Option<ObjectCollection>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shares: Option<ObjectCollection>,
+    /// Options of a single-choice poll
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub one_of: Vec<ObjectPollOption>,
+    /// Options of a multiple-choice poll
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub any_of: Vec<ObjectPollOption>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_time: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub closed: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub voters_count: Option<usize>,
+    #[serde(flatten)]
+    pub unknown: Map<String, Value>,
 }
 
 impl ObjectNote {
-    pub fn new(
-        id: &String,
-        attributed_to: &String,
-        in_reply_to: &String,
-        content: &String,
-    ) -> Self {
+    pub fn new(id: &str, attributed_to: &str, in_reply_to: Option<&str>, content: &str) -> Self {
         ObjectNote {
-            id: id.clone(),
+            id: id.to_string(),
             object_type: OBJECT_TYPE_NOTE.to_string(),
             published: Utc::now(),
-            attributed_to: attributed_to.clone(),
-            in_reply_to: in_reply_to.clone(),
-            content: content.clone(),
-            to: TO_PUBLIC.to_string(),
+            attributed_to: attributed_to.to_string(),
+            in_reply_to: in_reply_to.map(str::to_string),
+            name: None,
+            summary: None,
+            sensitive: None,
+            content: content.to_string(),
+            url: None,
+            to: TO_PUBLIC.to_string().into(),
+            cc: OneOrMany::default(),
+            tag: vec![],
+            attachment: vec![],
+            context: None,
+            conversation: None,
+            replies: None,
+            likes: None,
+            shares: None,
+            one_of: vec![],
+            any_of: vec![],
+            end_time: None,
+            closed: None,
+            voters_count: None,
+            unknown: Map::new(),
+        }
+    }
+
+    /// Place the note in a thread.
+    pub fn set_context(&mut self, context: &str) {
+        self.context = Some(context.to_string());
+        self.conversation = Some(context.to_string());
+    }
+
+    /// Turn the note into a Question offering the poll's options.
+    pub fn set_poll(&mut self, poll: &Poll) {
+        self.object_type = OBJECT_TYPE_QUESTION.to_string();
+        let options = poll
+            .options
+            .iter()
+            .map(|name| ObjectPollOption::new(name))
+            .collect();
+        if poll.multiple {
+            self.any_of = options;
+        } else {
+            self.one_of = options;
+        }
+        self.end_time = Some(poll.end_time);
+    }
+
+    /// Whether the note is a poll still accepting votes.
+    pub fn is_open_poll(&self) -> bool {
+        self.object_type == OBJECT_TYPE_QUESTION
+            && self.closed.is_none()
+            && self.end_time.is_some_and(|end_time| end_time > Utc::now())
+    }
+
+    /// Whether the note is addressed to the public collection, listed or not.
+    pub fn is_public(&self) -> bool {
+        self.to
+            .iter()
+            .chain(self.cc.iter())
+            .any(|id| id == TO_PUBLIC)
+    }
+
+    /// Whether `name` is one of the poll's options.
+    pub fn has_poll_option(&self, name: &str) -> bool {
+        self.one_of
+            .iter()
+            .chain(self.any_of.iter())
+            .any(|option| option.name == name)
+    }
+}
+
+/// What a new poll should offer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Poll {
+    pub options: Vec<String>,
+    /// Let voters choose more than one option
+    pub multiple: bool,
+    pub end_time: DateTime<Utc>,
+}
+
+/// One of the choices of a Question, with its votes counted as replies.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ObjectPollOption {
+    #[serde(rename = "type")]
+    pub object_type: String,
+    pub name: String,
+    pub replies: ObjectCollection,
+}
+
+impl ObjectPollOption {
+    pub fn new(name: &str) -> Self {
+        ObjectPollOption {
+            object_type: OBJECT_TYPE_NOTE.to_string(),
+            name: name.to_string(),
+            replies: ObjectCollection::tally(0),
         }
     }
 }
+
+/// A Mention, Hashtag or Emoji attached to an object.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ObjectTag {
+    #[serde(rename = "type")]
+    pub tag_type: String,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub href: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<ObjectImage>,
+}
+
+impl ObjectTag {
+    pub fn mention(name: &str, actor_id: &str) -> Self {
+        ObjectTag {
+            tag_type: TAG_TYPE_MENTION.to_string(),
+            name: name.to_string(),
+            href: Some(actor_id.to_string()),
+            id: None,
+            icon: None,
+        }
+    }
+
+    pub fn hashtag(name: &str, href: &str) -> Self {
+        ObjectTag {
+            tag_type: TAG_TYPE_HASHTAG.to_string(),
+            name: name.to_string(),
+            href: Some(href.to_string()),
+            id: None,
+            icon: None,
+        }
+    }
+
+    pub fn emoji(name: &str, icon: ObjectImage) -> Self {
+        ObjectTag {
+            tag_type: TAG_TYPE_EMOJI.to_string(),
+            name: name.to_string(),
+            href: None,
+            id: Some(icon.url.clone()),
+            icon: Some(icon),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ObjectImage {
+    #[serde(rename = "type")]
+    pub object_type: String,
+    pub media_type: String,
+    pub url: String,
+}
+
+impl ObjectImage {
+    pub fn new(media_type: &str, url: &str) -> Self {
+        ObjectImage {
+            object_type: OBJECT_TYPE_IMAGE.to_string(),
+            media_type: media_type.to_string(),
+            url: url.to_string(),
+        }
+    }
+}
+
+/// A name and value shown in a table on an actor's profile.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ObjectPropertyValue {
+    #[serde(rename = "type")]
+    pub object_type: String,
+    pub name: String,
+    /// HTML
+    pub value: String,
+}
+
+impl ObjectPropertyValue {
+    pub fn new(name: &str, value: &str) -> Self {
+        ObjectPropertyValue {
+            object_type: OBJECT_TYPE_PROPERTY_VALUE.to_string(),
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+}
+
+/// A media file attached to an object.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ObjectDocument {
+    #[serde(rename = "type")]
+    pub object_type: String,
+    pub media_type: String,
+    pub url: String,
+    /// Alt text
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blurhash: Option<String>,
+    /// Thumbnail
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<ObjectImage>,
+}
+
+impl ObjectDocument {
+    pub fn new(object_type: &str, media_type: &str, url: &str, name: Option<&str>) -> Self {
+        ObjectDocument {
+            object_type: object_type.to_string(),
+            media_type: media_type.to_string(),
+            url: url.to_string(),
+            name: name.map(str::to_string),
+            width: None,
+            height: None,
+            blurhash: None,
+            icon: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ObjectCollection {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(rename = "type")]
+    pub object_type: String,
+    pub total_items: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub items: Option<Vec<String>>,
+}
+
+impl ObjectCollection {
+    pub fn new(id: &str, items: Vec<String>) -> Self {
+        ObjectCollection {
+            id: Some(id.to_string()),
+            object_type: OBJECT_TYPE_COLLECTION.to_string(),
+            total_items: items.len(),
+            items: Some(items),
+        }
+    }
+
+    /// A collection that only reports how many items it has.
+    pub fn count_only(id: &str, total_items: usize) -> Self {
+        ObjectCollection {
+            id: Some(id.to_string()),
+            object_type: OBJECT_TYPE_COLLECTION.to_string(),
+            total_items,
+            items: None,
+        }
+    }
+
+    /// A bare count with no id, as used for the votes on a poll option.
+    pub fn tally(total_items: usize) -> Self {
+        ObjectCollection {
+            id: None,
+            object_type: OBJECT_TYPE_COLLECTION.to_string(),
+            total_items,
+            items: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ObjectOrderedCollection {
+    #[serde(rename = "@context")]
+    pub context: String,
+    pub id: String,
+    #[serde(rename = "type")]
+    pub object_type: String,
+    pub total_items: usize,
+    pub ordered_items: Vec<Value>,
+}
+
+impl ObjectOrderedCollection {
+    pub fn new(id: &str, ordered_items: Vec<Value>) -> Self {
+        ObjectOrderedCollection {
+            context: CONTEXT_ACTIVITYSTREAMS.to_string(),
+            id: id.to_string(),
+            object_type: OBJECT_TYPE_ORDERED_COLLECTION.to_string(),
+            total_items: ordered_items.len(),
+            ordered_items,
+        }
+    }
+}
+
+/// What remains of an object after it has been deleted.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ObjectTombstone {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub object_type: String,
+    pub former_type: String,
+    pub deleted: DateTime<Utc>,
+}
+
+impl ObjectTombstone {
+    pub fn new(id: &str, former_type: &str) -> Self {
+        ObjectTombstone {
+            id: id.to_string(),
+            object_type: OBJECT_TYPE_TOMBSTONE.to_string(),
+            former_type: former_type.to_string(),
+            deleted: Utc::now(),
+        }
+    }
+}
+
+impl Identified for ObjectNote {
+    fn id(&self) -> Option<&str> {
+        Some(&self.id)
+    }
+}
+
+impl Identified for ObjectTombstone {
+    fn id(&self) -> Option<&str> {
+        Some(&self.id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::activities::{self, Draft};
+    use crate::config;
+    use crate::content::ProcessedContent;
+    use crate::delivery::DeliveryQueue;
+    use crate::store::Store;
+    use crate::webfinger::WebfingerClient;
+    use actix_web::test as actix_test;
+    use actix_web::App;
+    use std::sync::Arc;
+
+    fn state() -> AppState {
+        let data = AppState {
+            public_key: String::new(),
+            store: Arc::new(Store::new()),
+            delivery: DeliveryQueue::start(),
+            webfinger: Arc::new(WebfingerClient::default()),
+        };
+        let domain = &config::CONFIG.primary_domain().domain;
+        data.store
+            .register_actor("a", ACTOR_TYPE_PERSON, domain)
+            .unwrap();
+        data
+    }
+
+    /// Send a request to a service with every object route, returning the
+    /// status and the body as JSON, or as a string if it isn't JSON.
+    async fn call(data: &AppState, request: actix_test::TestRequest) -> (u16, Value) {
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(web::Data::new(data.clone()))
+                .service(notes_service)
+                .service(likes_service)
+                .service(shares_service)
+                .service(outbox_service)
+                .service(object_page_service),
+        )
+        .await;
+        let response = actix_test::call_service(&app, request.to_request()).await;
+        let status = response.status().as_u16();
+        let body = actix_test::read_body(response).await;
+        let body = serde_json::from_slice(&body)
+            .unwrap_or_else(|_| Value::from(String::from_utf8_lossy(&body).to_string()));
+        (status, body)
+    }
+
+    async fn get(data: &AppState, uri: &str) -> (u16, Value) {
+        call(data, actix_test::TestRequest::get().uri(uri)).await
+    }
+
+    #[actix_web::test]
+    async fn test_threads_leave_out_non_public_objects() {
+        let data = state();
+        let actor = actors::actor_lookup(&data, "a").unwrap();
+        let note = activities::publish_note(&data, &actor, Draft::default()).await;
+        let reply = |id: &str, in_reply_to: &str, to: &str| {
+            data.store.put_remote_object(
+                id,
+                serde_json::json!({
+                    "id": id,
+                    "type": "Note",
+                    "inReplyTo": in_reply_to,
+                    "to": to,
+                    "content": "",
+                }),
+            )
+        };
+        reply("https://remote.example/notes/1", &note.id, TO_PUBLIC);
+        reply(
+            "https://remote.example/notes/2",
+            &note.id,
+            "https://remote.example/users/b/followers",
+        );
+        reply(
+            "https://remote.example/notes/3",
+            "https://remote.example/notes/2",
+            TO_PUBLIC,
+        );
+
+        let thread = thread_tree(&data, "https://remote.example/notes/1").unwrap();
+        assert_eq!(thread.object["id"], note.id);
+        let reply_ids: Vec<_> = thread
+            .replies
+            .iter()
+            .map(|reply| reply.object["id"].clone())
+            .collect();
+        assert_eq!(reply_ids, ["https://remote.example/notes/1"]);
+
+        // A public reply to a private one can't show where it belongs.
+        let thread = thread_tree(&data, "https://remote.example/notes/3").unwrap();
+        assert_eq!(thread.object["id"], "https://remote.example/notes/3");
+        assert!(thread_tree(&data, "https://remote.example/notes/2").is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_shares_collection_and_outbox() {
+        let data = state();
+        let actor = actors::actor_lookup(&data, "a").unwrap();
+        let note = activities::publish_note(&data, &actor, Draft::default()).await;
+        let note_id = note.id.rsplit('/').next().unwrap();
+        let shares_uri = format!("/@a/notes/{}/shares.json", note_id);
+
+        let (status, shares) = get(&data, &shares_uri).await;
+        assert_eq!(status, 200);
+        assert_eq!(shares["totalItems"], 0);
+
+        let announce = activities::announce(&data, &actor, &note.id).await.unwrap();
+        data.store.add_interaction(
+            Interaction::Announce,
+            &note.id,
+            "https://remote.example/users/b",
+            "https://remote.example/announces/1",
+        );
+        let (_, shares) = get(&data, &shares_uri).await;
+        assert_eq!(
+            shares["id"],
+            actor.interactions_url(ObjectKind::Note, note_id, Interaction::Announce)
+        );
+        assert_eq!(
+            shares["items"],
+            serde_json::json!([actor.actor_id(), "https://remote.example/users/b"])
+        );
+        let (_, likes) = get(&data, &format!("/@a/notes/{}/likes.json", note_id)).await;
+        assert_eq!(likes["totalItems"], 0);
+
+        let (status, outbox) = get(&data, "/@a/outbox").await;
+        assert_eq!(status, 200);
+        assert_eq!(outbox["totalItems"], 2);
+        assert_eq!(outbox["orderedItems"][0]["id"], announce.id.as_str());
+        assert_eq!(outbox["orderedItems"][1]["object"]["id"], note.id.as_str());
+
+        activities::unannounce(&data, &actor, &note.id).unwrap();
+        let (_, shares) = get(&data, &shares_uri).await;
+        assert_eq!(shares["totalItems"], 1);
+        let (_, outbox) = get(&data, "/@a/outbox").await;
+        assert_eq!(outbox["totalItems"], 1);
+
+        assert_eq!(get(&data, "/@a/notes/missing/shares.json").await.0, 404);
+        assert_eq!(get(&data, "/@nobody/outbox").await.0, 404);
+    }
+
+    async fn publish(data: &AppState, kind: ObjectKind, name: &str) -> ObjectNote {
+        let actor = actors::actor_lookup(data, "a").unwrap();
+        let draft = Draft {
+            kind,
+            name: Some(name.to_string()),
+            summary: Some("What it is about".to_string()),
+            content: ProcessedContent {
+                html: "<p>Long form</p>".to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        activities::publish_note(data, &actor, draft).await
+    }
+
+    #[actix_web::test]
+    async fn test_article_routes() {
+        let data = state();
+        let article = publish(&data, ObjectKind::Article, "A <title>").await;
+        let article_id = article.id.rsplit('/').next().unwrap();
+        assert!(article
+            .id
+            .ends_with(&format!("/@a/articles/{}", article_id)));
+        assert_eq!(article.url.as_deref(), Some(article.id.as_str()));
+
+        let (status, page) = get(&data, &format!("/@a/articles/{}", article_id)).await;
+        assert_eq!(status, 200);
+        let page = page.as_str().unwrap();
+        assert!(page.contains("<h1>A &lt;title&gt;</h1>"));
+        assert!(page.contains(r#"<meta property="og:type" content="article">"#));
+        assert!(page.contains(r#"<meta property="og:description" content="What it is about">"#));
+        assert!(page.contains("<div class=\"summary\">What it is about</div>"));
+        assert!(page.contains("<p>Long form</p>"));
+
+        let request = actix_test::TestRequest::get()
+            .uri(&format!("/@a/articles/{}", article_id))
+            .insert_header(("accept", MEDIA_TYPE_ACTIVITY_JSON));
+        let (status, object) = call(&data, request).await;
+        assert_eq!(status, 200);
+        assert_eq!(object["type"], OBJECT_TYPE_ARTICLE);
+        assert_eq!(object["name"], "A <title>");
+        assert_eq!(object["summary"], "What it is about");
+        assert_eq!(
+            object["replies"]["id"],
+            format!("{}/replies.json", article.id)
+        );
+
+        let (status, object) = get(&data, &format!("/@a/articles/{}.json", article_id)).await;
+        assert_eq!(status, 200);
+        assert_eq!(object["id"], article.id.as_str());
+
+        // Each kind is only served from its own routes.
+        for uri in [
+            format!("/@a/notes/{}", article_id),
+            format!("/@a/pages/{}.json", article_id),
+            format!("/@a/posts/{}", article_id),
+        ] {
+            assert_eq!(get(&data, &uri).await.0, 404, "{}", uri);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_page_routes() {
+        let data = state();
+        let page = publish(&data, ObjectKind::Page, "About").await;
+        let page_id = page.id.rsplit('/').next().unwrap();
+
+        let (status, object) = get(&data, &format!("/@a/pages/{}.json", page_id)).await;
+        assert_eq!(status, 200);
+        assert_eq!(object["type"], OBJECT_TYPE_PAGE);
+        assert_eq!(object["name"], "About");
+        let (status, html) = get(&data, &format!("/@a/pages/{}", page_id)).await;
+        assert_eq!(status, 200);
+        assert!(html.as_str().unwrap().contains("<h1>About</h1>"));
+        assert_eq!(
+            get(&data, &format!("/@a/articles/{}", page_id)).await.0,
+            404
+        );
+    }
+}
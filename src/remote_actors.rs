@@ -0,0 +1,311 @@
+use chrono::{DateTime, Duration, Utc};
+use log::{info, warn};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::actors::{self, LocalActorPerson};
+use crate::app::AppState;
+use crate::constants::*;
+use crate::fetch::{self, FetchError};
+use crate::objects::ObjectPropertyValue;
+use crate::sanitize;
+
+/// Values of `type` that make an object an actor.
+const ACTOR_TYPES: &[&str] = &[
+    ACTOR_TYPE_PERSON,
+    ACTOR_TYPE_APPLICATION,
+    ACTOR_TYPE_GROUP,
+    ACTOR_TYPE_SERVICE,
+    ACTOR_TYPE_ORGANIZATION,
+];
+
+/// How long a fetched actor is trusted before it is fetched again.
+const MAX_AGE_HOURS: i64 = 24;
+
+/// How often stale actors are refreshed in the background.
+const REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+/// Whether an object type is one of the actor types.
+pub fn is_actor_type(object_type: &str) -> bool {
+    ACTOR_TYPES.contains(&object_type)
+}
+
+/// A remote actor as far as we need to know it: where to deliver to, how to
+/// verify its signatures and what to show on its profile.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemoteActor {
+    pub id: String,
+    pub actor_type: String,
+    pub preferred_username: Option<String>,
+    pub name: Option<String>,
+    /// HTML, sanitized
+    pub summary: Option<String>,
+    pub url: Option<String>,
+    pub icon: Option<String>,
+    pub inbox: String,
+    pub shared_inbox: Option<String>,
+    pub outbox: Option<String>,
+    pub followers: Option<String>,
+    pub public_key: Option<RemotePublicKey>,
+    /// Profile fields, with sanitized HTML values.
+    pub fields: Vec<ObjectPropertyValue>,
+    pub fetched_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RemotePublicKey {
+    pub id: String,
+    pub owner: String,
+    pub public_key_pem: String,
+}
+
+impl RemoteActor {
+    /// Read an actor from its (normalised) ActivityPub representation.
+    pub fn from_value(value: &Value, fetched_at: DateTime<Utc>) -> Result<Self, FetchError> {
+        let string = |property: &str| value[property].as_str().map(str::to_string);
+        let actor_type = string("type")
+            .filter(|actor_type| is_actor_type(actor_type))
+            .ok_or(FetchError::InvalidResponse)?;
+        let id = string("id").ok_or(FetchError::InvalidResponse)?;
+        let inbox = string("inbox").ok_or(FetchError::InvalidResponse)?;
+
+        // Some servers list several keys; we only need the one the actor owns.
+        let keys = match &value["publicKey"] {
+            Value::Array(keys) => keys.clone(),
+            key => vec![key.clone()],
+        };
+        let public_key = keys
+            .into_iter()
+            .filter_map(|key| serde_json::from_value::<RemotePublicKey>(key).ok())
+            .find(|key| key.owner == id);
+
+        let fields = match &value["attachment"] {
+            Value::Array(attachments) => attachments.clone(),
+            attachment => vec![attachment.clone()],
+        }
+        .into_iter()
+        .filter_map(|attachment| serde_json::from_value::<ObjectPropertyValue>(attachment).ok())
+        .filter(|field| field.object_type == OBJECT_TYPE_PROPERTY_VALUE)
+        .map(|field| ObjectPropertyValue::new(&field.name, &sanitize::sanitize_html(&field.value)))
+        .collect();
+
+        Ok(RemoteActor {
+            actor_type,
+            preferred_username: string("preferredUsername"),
+            name: string("name"),
+            summary: value["summary"].as_str().map(sanitize::sanitize_html),
+            url: url_of(&value["url"], "href"),
+            icon: url_of(&value["icon"], "url"),
+            shared_inbox: value["endpoints"]["sharedInbox"]
+                .as_str()
+                .map(str::to_string),
+            outbox: string("outbox"),
+            followers: string("followers"),
+            public_key,
+            fields,
+            fetched_at,
+            id,
+            inbox,
+        })
+    }
+
+    /// The inbox to deliver public activities to, preferring the shared one.
+    pub fn delivery_inbox(&self) -> &str {
+        self.shared_inbox.as_deref().unwrap_or(&self.inbox)
+    }
+
+    pub fn is_stale(&self, now: DateTime<Utc>) -> bool {
+        now - self.fetched_at > Duration::hours(MAX_AGE_HOURS)
+    }
+}
+
+/// The URL in a property given as a bare IRI, an object holding it under
+/// `key`, or an array of either.
+fn url_of(value: &Value, key: &str) -> Option<String> {
+    match value {
+        Value::String(url) => Some(url.clone()),
+        Value::Array(values) => values.iter().find_map(|value| url_of(value, key)),
+        Value::Object(object) => object.get(key).and_then(|value| url_of(value, key)),
+        _ => None,
+    }
+}
+
+/// The local actor background fetches are signed as. Every local actor shares
+/// one key pair, so any will do; the first by name keeps the key id stable
+/// for servers that cache it.
+fn signer(data: &AppState) -> Option<LocalActorPerson> {
+    let mut names = data.store.active_actors();
+    names.sort();
+    names
+        .iter()
+        .find_map(|name| actors::actor_lookup(data, name).ok())
+}
+
+/// A remote actor, from the store while it is fresh and fetched otherwise.
+/// A stale copy is still used if the actor's server can't be reached.
+pub async fn remote_actor(data: &AppState, actor_id: &str) -> Result<RemoteActor, FetchError> {
+    let cached = data.store.get_remote_actor(actor_id);
+    match cached {
+        Some(actor) if !actor.is_stale(Utc::now()) => Ok(actor),
+        _ => match fetch_remote_actor(data, actor_id).await {
+            Err(err) => cached.ok_or(err),
+            actor => actor,
+        },
+    }
+}
+
+/// Dereference a remote actor with a signed GET and store it.
+pub async fn fetch_remote_actor(
+    data: &AppState,
+    actor_id: &str,
+) -> Result<RemoteActor, FetchError> {
+    let value = fetch_signed(data, actor_id).await?;
+    let actor = RemoteActor::from_value(&value, Utc::now())?;
+    data.store.put_remote_actor(actor.clone());
+    Ok(actor)
+}
+
+/// The actor owning the key `key_id`, which it must list as its own. Most
+/// keys are a fragment of their owner's document; others are documents of
+/// their own naming the owner.
+pub async fn key_owner(data: &AppState, key_id: &str) -> Result<RemoteActor, FetchError> {
+    let document = key_id.split('#').next().unwrap_or_default();
+    if let Some(actor) = data.store.get_remote_actor(document) {
+        if owns_key(&actor, key_id) && !actor.is_stale(Utc::now()) {
+            return Ok(actor);
+        }
+    }
+
+    let value = fetch_signed(data, document).await?;
+    let owner = if value["type"].as_str().is_some_and(is_actor_type) {
+        let actor = RemoteActor::from_value(&value, Utc::now())?;
+        data.store.put_remote_actor(actor.clone());
+        actor
+    } else {
+        let key = serde_json::from_value::<RemotePublicKey>(value)
+            .map_err(|_| FetchError::InvalidResponse)?;
+        remote_actor(data, &key.owner).await?
+    };
+    if owns_key(&owner, key_id) {
+        return Ok(owner);
+    }
+    // The owner may have added the key since we stored it.
+    match fetch_remote_actor(data, &owner.id).await {
+        Ok(owner) if owns_key(&owner, key_id) => Ok(owner),
+        Ok(_) => Err(FetchError::InvalidResponse),
+        Err(err) => Err(err),
+    }
+}
+
+fn owns_key(actor: &RemoteActor, key_id: &str) -> bool {
+    actor
+        .public_key
+        .as_ref()
+        .is_some_and(|key| key.id == key_id)
+}
+
+async fn fetch_signed(data: &AppState, url: &str) -> Result<Value, FetchError> {
+    match signer(data) {
+        Some(signer) => fetch::fetch_object_signed(url, &signer).await,
+        None => fetch::fetch_object(url).await,
+    }
+}
+
+/// Fetch an actor again in the background, e.g. after it sent an `Update`.
+pub async fn refresh_remote_actor(data: AppState, actor_id: String) {
+    match fetch_remote_actor(&data, &actor_id).await {
+        Ok(_) => info!("Refreshed remote actor {}", actor_id),
+        Err(err) => warn!("Could not refresh remote actor {}: {:?}", actor_id, err),
+    }
+}
+
+/// Periodically fetch again every stored actor that has gone stale.
+pub async fn refresh_stale_actors(data: AppState) {
+    loop {
+        actix_web::rt::time::sleep(REFRESH_INTERVAL).await;
+        for actor_id in data.store.stale_remote_actors(Utc::now()) {
+            refresh_remote_actor(data.clone(), actor_id).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_from_mastodon_actor() {
+        let value = json!({
+            "id": "https://mastodon.example/users/alice",
+            "type": "Person",
+            "preferredUsername": "alice",
+            "name": "Alice",
+            "summary": "<p>Hi<script>alert(1)</script></p>",
+            "url": "https://mastodon.example/@alice",
+            "icon": {"type": "Image", "url": "https://mastodon.example/a.png"},
+            "inbox": "https://mastodon.example/users/alice/inbox",
+            "outbox": "https://mastodon.example/users/alice/outbox",
+            "followers": "https://mastodon.example/users/alice/followers",
+            "endpoints": {"sharedInbox": "https://mastodon.example/inbox"},
+            "publicKey": {
+                "id": "https://mastodon.example/users/alice#main-key",
+                "owner": "https://mastodon.example/users/alice",
+                "publicKeyPem": "-----BEGIN PUBLIC KEY-----"
+            },
+            "attachment": [
+                {"type": "PropertyValue", "name": "Site", "value": "<a href=\"https://a.example\">a</a>"},
+                {"type": "Image", "url": "https://mastodon.example/b.png"}
+            ]
+        });
+        let now = Utc::now();
+        let actor = RemoteActor::from_value(&value, now).unwrap();
+        assert_eq!(actor.summary.as_deref(), Some("<p>Hi</p>"));
+        assert_eq!(
+            actor.icon.as_deref(),
+            Some("https://mastodon.example/a.png")
+        );
+        assert_eq!(actor.delivery_inbox(), "https://mastodon.example/inbox");
+        assert_eq!(
+            actor.followers.as_deref(),
+            Some("https://mastodon.example/users/alice/followers")
+        );
+        assert_eq!(
+            actor.public_key.as_ref().unwrap().id,
+            "https://mastodon.example/users/alice#main-key"
+        );
+        assert_eq!(actor.fields.len(), 1);
+        assert_eq!(actor.fields[0].name, "Site");
+        assert!(!actor.is_stale(now));
+        assert!(actor.is_stale(now + Duration::hours(25)));
+    }
+
+    #[test]
+    fn test_from_value_requires_an_actor() {
+        let now = Utc::now();
+        let note = json!({"id": "https://a.example/1", "type": "Note", "inbox": "x"});
+        assert_eq!(
+            RemoteActor::from_value(&note, now),
+            Err(FetchError::InvalidResponse)
+        );
+        let no_inbox = json!({"id": "https://a.example/1", "type": "Service"});
+        assert_eq!(
+            RemoteActor::from_value(&no_inbox, now),
+            Err(FetchError::InvalidResponse)
+        );
+        // A key owned by someone else is not the actor's key.
+        let foreign_key = json!({
+            "id": "https://a.example/1",
+            "type": "Service",
+            "inbox": "https://a.example/1/inbox",
+            "publicKey": {"id": "k", "owner": "https://b.example/2", "publicKeyPem": "pem"}
+        });
+        assert_eq!(
+            RemoteActor::from_value(&foreign_key, now)
+                .unwrap()
+                .public_key,
+            None
+        );
+    }
+}
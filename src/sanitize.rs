@@ -0,0 +1,116 @@
+use std::collections::{HashMap, HashSet};
+
+use ammonia::Builder;
+use serde_json::Value;
+
+lazy_static! {
+    /// Allowlist covering the HTML subset fediverse software commonly emits.
+    static ref SANITIZER: Builder<'static> = {
+        let mut builder = Builder::empty();
+        builder
+            .add_tags(&[
+                "p", "br", "a", "span", "strong", "b", "em", "i", "u", "del", "s", "pre",
+                "code", "blockquote", "ul", "ol", "li", "h1", "h2", "h3", "h4", "h5", "h6",
+            ])
+            .add_tag_attributes("a", &["href", "rel"])
+            .add_tag_attributes("ol", &["start", "reversed"])
+            .allowed_classes(HashMap::from([
+                ("a", HashSet::from(["mention", "hashtag", "u-url", "status-link"])),
+                (
+                    "span",
+                    HashSet::from(["h-card", "mention", "hashtag", "invisible", "ellipsis"]),
+                ),
+            ]))
+            .url_schemes(HashSet::from(["http", "https", "mailto"]))
+            .link_rel(None)
+            .attribute_filter(|element, attribute, value| {
+                if element == "a" && attribute == "rel" {
+                    let rel: Vec<&str> = value
+                        .split_whitespace()
+                        .filter(|rel| ALLOWED_RELS.contains(rel))
+                        .collect();
+                    if rel.is_empty() {
+                        return None;
+                    }
+                    return Some(rel.join(" ").into());
+                }
+                Some(value.into())
+            });
+        builder
+    };
+}
+
+/// Values of `rel` kept on links; anything else is dropped.
+const ALLOWED_RELS: &[&str] = &["tag", "me", "nofollow", "noopener", "noreferrer"];
+
+/// Properties of an object that may carry HTML.
+const HTML_PROPERTIES: &[&str] = &["content", "summary"];
+
+/// Strip everything outside the allowlist from an HTML fragment.
+pub fn sanitize_html(html: &str) -> String {
+    SANITIZER.clean(html).to_string()
+}
+
+/// Sanitize every HTML property of an object in place, including the
+/// per-language variants in `contentMap` and friends.
+pub fn sanitize_object(object: &mut Value) {
+    for property in HTML_PROPERTIES {
+        if let Some(Value::String(html)) = object.get_mut(*property) {
+            *html = sanitize_html(html);
+        }
+        if let Some(Value::Object(map)) = object.get_mut(format!("{}Map", property)) {
+            for html in map.values_mut() {
+                if let Value::String(html) = html {
+                    *html = sanitize_html(html);
+                }
+            }
+        }
+    }
+}
+
+/// Reduce an HTML fragment to plain text, for previews and search. Paragraph
+/// and line breaks become newlines.
+pub fn to_plain_text(html: &str) -> String {
+    let html = html
+        .replace("<br>", "\n")
+        .replace("<br/>", "\n")
+        .replace("<br />", "\n")
+        .replace("</p>", "</p>\n\n");
+    let text = Builder::empty().clean(&html).to_string();
+    text.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_keeps_fediverse_subset() {
+        let html = r#"<p>hi <span class="h-card"><a href="https://example.com/@a" class="u-url mention">@<span>a</span></a></span> <a href="https://example.com/tags/x" class="mention hashtag evil" rel="tag nofollow external">#x</a></p>"#;
+        assert_eq!(
+            sanitize_html(html),
+            r#"<p>hi <span class="h-card"><a href="https://example.com/@a" class="u-url mention">@<span>a</span></a></span> <a href="https://example.com/tags/x" class="mention hashtag" rel="tag nofollow">#x</a></p>"#
+        );
+    }
+
+    #[test]
+    fn test_sanitize_strips_scripts_and_handlers() {
+        let html = r#"<p onclick="x()">a<script>alert(1)</script><img src="x"><a href="javascript:alert(1)">b</a></p>"#;
+        assert_eq!(sanitize_html(html), "<p>a<a>b</a></p>");
+    }
+
+    #[test]
+    fn test_to_plain_text() {
+        assert_eq!(
+            to_plain_text("<p>one &amp; <b>two</b><br>three</p><p>four</p>"),
+            "one & two\nthree\n\nfour"
+        );
+    }
+}
@@ -1,6 +1,8 @@
 extern crate dotenv;
 
-use log::{info};
+use std::{fs, io};
+
+use log::{info, warn};
 
 use actix_web::{middleware::Logger, web, App, HttpServer};
 use actix_files::Files;
@@ -8,15 +10,6 @@ use actix_files::Files;
 use rust_activitypub_play::*;
 use rust_activitypub_play::{config, config::CONFIG};
 
-// use std::fs::File;
-use std::fs;
-use std::io::BufReader;
-use std::io::prelude::*;
-use std::fs::File;
-use std::error::Error;
-use std::env;
-
-
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     config::init();
@@ -28,13 +21,92 @@ async fn main() -> std::io::Result<()> {
         CONFIG.domain
     );
 
-    HttpServer::new(|| {
+    let state = web::Data::new(app::AppState::new());
+    // Deleted actors keep their names, even those configured below.
+    actors::load_deleted_actors(&state.store, &CONFIG.deleted_actors_file)?;
+    let actors = CONFIG
+        .local_actors
+        .iter()
+        .map(|name| (name, constants::ACTOR_TYPE_APPLICATION))
+        .chain(CONFIG.group_actors.iter().map(|name| (name, constants::ACTOR_TYPE_GROUP)));
+    for (entry, actor_type) in actors {
+        // Entries are `name` on the primary domain, or `name@domain`.
+        let (name, domain) = match entry.split_once('@') {
+            Some((name, domain)) => (name, domain),
+            None => (entry.as_str(), CONFIG.domain.as_str()),
+        };
+        let result = match CONFIG.hosted_domain(domain) {
+            None => {
+                warn!("Could not register actor {}: {} is not hosted here", entry, domain);
+                continue;
+            }
+            Some(hosted) => state.store.register_actor(name, actor_type, &hosted.domain),
+        };
+        match result {
+            Ok(()) => (),
+            Err(store::RegisterError::Reserved) => {
+                info!("Not registering {}: the actor was deleted", entry)
+            }
+            Err(err) => warn!("Could not register actor {}: {:?}", entry, err),
+        }
+    }
+
+    // Policies set through the admin API live in memory only; these are the
+    // ones that survive a restart.
+    let policies_csv = match &CONFIG.domain_policies_file {
+        Some(path) => Some(fs::read_to_string(path)?),
+        None => None,
+    };
+    moderation::seed(
+        &state.store,
+        policies_csv.as_deref(),
+        &CONFIG.blocked_domains,
+        &CONFIG.allowed_domains,
+    )
+    .map_err(|err| io::Error::other(format!("Could not load domain policies: {:?}", err)))?;
+
+    actix_web::rt::spawn(remote_actors::refresh_stale_actors(state.get_ref().clone()));
+
+    HttpServer::new(move || {
         App::new()
-            .app_data(web::Data::new(app::AppState::new()))
+            .app_data(state.clone())
             .service(webfinger::resolver_service)
+            .service(webfinger::host_meta_service)
+            .service(webfinger::host_meta_json_service)
+            .service(nodeinfo::discovery_service)
+            .service(nodeinfo::nodeinfo_service)
+            .service(webfinger::authorize_interaction_service)
             .service(actors::actors_service)
+            .service(actors::actor_page_service)
             .service(objects::notes_service)
+            .service(objects::replies_service)
+            .service(objects::likes_service)
+            .service(objects::shares_service)
+            .service(objects::outbox_service)
+            .service(objects::object_page_service)
             .service(activities::activities_service)
+            .service(inbox::shared_inbox_service)
+            .service(inbox::inbox_service)
+            .service(api::publish_note_service)
+            .service(api::delete_note_service)
+            .service(api::thread_service)
+            .service(api::upload_media_service)
+            .service(api::like_service)
+            .service(api::unlike_service)
+            .service(api::announce_service)
+            .service(api::unannounce_service)
+            .service(media::media_service)
+            .service(api::register_actor_service)
+            .service(api::update_profile_service)
+            .service(api::delete_actor_service)
+            .service(api::domain_policies_service)
+            .service(api::export_domain_policies_service)
+            .service(api::import_domain_policies_service)
+            .service(api::put_domain_policy_service)
+            .service(api::delete_domain_policy_service)
+            .service(api::domain_allows_service)
+            .service(api::allow_domain_service)
+            .service(api::disallow_domain_service)
             .service(Files::new("/", "./static/").index_file("index.html"))
             .wrap(Logger::default())
     })
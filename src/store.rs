@@ -0,0 +1,706 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use chrono::prelude::*;
+use serde_json::Value;
+
+use crate::actors::ActorProfile;
+use crate::constants::*;
+use crate::moderation::DomainPolicy;
+use crate::objects::{ObjectDocument, ObjectNote, ObjectTombstone};
+use crate::remote_actors::RemoteActor;
+
+/// A locally authored object, or the tombstone left behind once it is deleted.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StoredObject {
+    Note(Box<ObjectNote>),
+    Tombstone(ObjectTombstone),
+}
+
+/// Registration state of a local actor, holding its type and the hosted
+/// domain it belongs to while active. Deleted actors keep their entry so the
+/// username can never be registered again, on any domain.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ActorStatus {
+    Active { actor_type: String, domain: String },
+    Deleted(ObjectTombstone),
+}
+
+/// An error that occured while registering a local actor.
+#[derive(Debug, PartialEq)]
+pub enum RegisterError {
+    /// An active actor already uses the name.
+    Taken,
+    /// The name belonged to a deleted actor and stays reserved.
+    Reserved,
+}
+
+/// Ways an actor can interact with an object that we keep count of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Interaction {
+    Like,
+    Announce,
+}
+
+impl Interaction {
+    /// The interaction an activity type records, if it records one.
+    pub fn from_activity_type(activity_type: &str) -> Option<Self> {
+        if activity_type == ACTIVITY_TYPE_LIKE {
+            Some(Interaction::Like)
+        } else if activity_type == ACTIVITY_TYPE_ANNOUNCE {
+            Some(Interaction::Announce)
+        } else {
+            None
+        }
+    }
+
+    pub fn activity_type(&self) -> &'static str {
+        match self {
+            Interaction::Like => ACTIVITY_TYPE_LIKE,
+            Interaction::Announce => ACTIVITY_TYPE_ANNOUNCE,
+        }
+    }
+
+    /// Name of the collection of an object's interactions of this kind.
+    pub fn collection(&self) -> &'static str {
+        match self {
+            Interaction::Like => "likes",
+            Interaction::Announce => "shares",
+        }
+    }
+}
+
+/// In-memory storage shared by every worker of the server.
+#[derive(Default)]
+pub struct Store {
+    inner: RwLock<StoreInner>,
+}
+
+#[derive(Default)]
+struct StoreInner {
+    /// Local actors keyed by name.
+    actors: HashMap<String, ActorStatus>,
+    /// Local objects keyed by id.
+    objects: HashMap<String, StoredObject>,
+    /// Inboxes each local object was delivered to, keyed by object id.
+    recipients: HashMap<String, BTreeSet<String>>,
+    /// Every inbox each local actor has delivered to, keyed by actor name.
+    delivered: HashMap<String, BTreeSet<String>>,
+    /// Followers of each local actor, mapping follower actor id to its inbox.
+    followers: HashMap<String, HashMap<String, String>>,
+    /// Objects received from other servers, keyed by id.
+    remote_objects: HashMap<String, Value>,
+    /// Actors on other servers, keyed by id.
+    remote_actors: HashMap<String, RemoteActor>,
+    /// Known replies to each object, local or remote, keyed by the id of the
+    /// object replied to.
+    replies: HashMap<String, BTreeSet<String>>,
+    /// Likes and announces of each object, local or remote, mapping the id
+    /// of the interacting actor to the id of its activity.
+    interactions: HashMap<Interaction, HashMap<String, BTreeMap<String, String>>>,
+    /// Votes on each local poll, mapping the id of each voter to the options
+    /// they chose.
+    votes: HashMap<String, BTreeMap<String, BTreeSet<String>>>,
+    /// Activities published by each local actor, oldest first, keyed by
+    /// actor name.
+    outbox: HashMap<String, Vec<Value>>,
+    /// Profiles of local actors, keyed by name.
+    profiles: HashMap<String, ActorProfile>,
+    /// Uploaded media, keyed by stored file name.
+    media: HashMap<String, ObjectDocument>,
+    /// Moderation policies for remote domains, keyed by domain.
+    domain_policies: BTreeMap<String, DomainPolicy>,
+    /// Domains we federate with in allowlist mode.
+    allowed_domains: BTreeSet<String>,
+}
+
+impl StoreInner {
+    fn add_reply(&mut self, in_reply_to: Option<&str>, id: &str) {
+        if let Some(in_reply_to) = in_reply_to {
+            self.replies
+                .entry(in_reply_to.to_string())
+                .or_default()
+                .insert(id.to_string());
+        }
+    }
+
+    fn remove_reply(&mut self, in_reply_to: Option<&str>, id: &str) {
+        if let Some(replies) = in_reply_to.and_then(|parent| self.replies.get_mut(parent)) {
+            replies.remove(id);
+        }
+    }
+}
+
+impl Store {
+    pub fn new() -> Self {
+        Store::default()
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, StoreInner> {
+        self.inner.read().expect("store lock should not be poisoned")
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, StoreInner> {
+        self.inner.write().expect("store lock should not be poisoned")
+    }
+
+    pub fn register_actor(
+        &self,
+        name: &str,
+        actor_type: &str,
+        domain: &str,
+    ) -> Result<(), RegisterError> {
+        let mut inner = self.write();
+        match inner.actors.get(name) {
+            Some(ActorStatus::Active { .. }) => Err(RegisterError::Taken),
+            Some(ActorStatus::Deleted(_)) => Err(RegisterError::Reserved),
+            None => {
+                inner.actors.insert(
+                    name.to_string(),
+                    ActorStatus::Active {
+                        actor_type: actor_type.to_string(),
+                        domain: domain.to_string(),
+                    },
+                );
+                Ok(())
+            }
+        }
+    }
+
+    pub fn actor_status(&self, name: &str) -> Option<ActorStatus> {
+        self.read().actors.get(name).cloned()
+    }
+
+    /// Mark a local actor as deleted, leaving `tombstone` in its place.
+    /// Returns `false` if there was no active actor with the name.
+    pub fn delete_actor(&self, name: &str, tombstone: ObjectTombstone) -> bool {
+        let mut inner = self.write();
+        match inner.actors.get(name) {
+            Some(ActorStatus::Active { .. }) => {
+                inner
+                    .actors
+                    .insert(name.to_string(), ActorStatus::Deleted(tombstone));
+                inner.profiles.remove(name);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn profile(&self, name: &str) -> ActorProfile {
+        self.read().profiles.get(name).cloned().unwrap_or_default()
+    }
+
+    pub fn set_profile(&self, name: &str, profile: ActorProfile) {
+        self.write().profiles.insert(name.to_string(), profile);
+    }
+
+    pub fn get_object(&self, id: &str) -> Option<StoredObject> {
+        self.read().objects.get(id).cloned()
+    }
+
+    pub fn put_note(&self, note: ObjectNote) {
+        let mut inner = self.write();
+        inner.add_reply(note.in_reply_to.as_deref(), &note.id);
+        inner
+            .objects
+            .insert(note.id.clone(), StoredObject::Note(Box::new(note)));
+    }
+
+    /// Mark a poll as closed, returning `false` if there is no open poll
+    /// stored under the id.
+    pub fn close_poll(&self, id: &str, closed: DateTime<Utc>) -> bool {
+        match self.write().objects.get_mut(id) {
+            Some(StoredObject::Note(note))
+                if note.object_type == OBJECT_TYPE_QUESTION && note.closed.is_none() =>
+            {
+                note.closed = Some(closed);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Replace a stored note with a tombstone, returning the tombstone.
+    /// Returns `None` if there was no note stored under the id.
+    pub fn tombstone_object(&self, id: &str) -> Option<ObjectTombstone> {
+        let mut inner = self.write();
+        let (former_type, in_reply_to) = match inner.objects.get(id)? {
+            StoredObject::Note(note) => (note.object_type.clone(), note.in_reply_to.clone()),
+            StoredObject::Tombstone(_) => return None,
+        };
+        inner.remove_reply(in_reply_to.as_deref(), id);
+        let tombstone = ObjectTombstone::new(id, &former_type);
+        inner
+            .objects
+            .insert(id.to_string(), StoredObject::Tombstone(tombstone.clone()));
+        Some(tombstone)
+    }
+
+    pub fn add_recipients(&self, actor_name: &str, object_id: &str, inboxes: &[String]) {
+        let mut inner = self.write();
+        inner
+            .recipients
+            .entry(object_id.to_string())
+            .or_default()
+            .extend(inboxes.iter().cloned());
+        inner
+            .delivered
+            .entry(actor_name.to_string())
+            .or_default()
+            .extend(inboxes.iter().cloned());
+    }
+
+    pub fn recipients(&self, object_id: &str) -> Vec<String> {
+        self.read()
+            .recipients
+            .get(object_id)
+            .map(|inboxes| inboxes.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn add_follower(&self, actor_name: &str, follower_id: &str, inbox: &str) {
+        self.write()
+            .followers
+            .entry(actor_name.to_string())
+            .or_default()
+            .insert(follower_id.to_string(), inbox.to_string());
+    }
+
+    /// Distinct inboxes of everyone following the given local actor.
+    pub fn follower_inboxes(&self, actor_name: &str) -> Vec<String> {
+        let inner = self.read();
+        let inboxes: BTreeSet<String> = inner
+            .followers
+            .get(actor_name)
+            .map(|followers| followers.values().cloned().collect())
+            .unwrap_or_default();
+        inboxes.into_iter().collect()
+    }
+
+    /// Every inbox a local actor is known to: its followers' and any it has
+    /// delivered to.
+    pub fn known_inboxes(&self, actor_name: &str) -> Vec<String> {
+        let inner = self.read();
+        let mut inboxes: BTreeSet<String> = inner
+            .delivered
+            .get(actor_name)
+            .cloned()
+            .unwrap_or_default();
+        if let Some(followers) = inner.followers.get(actor_name) {
+            inboxes.extend(followers.values().cloned());
+        }
+        inboxes.into_iter().collect()
+    }
+
+    pub fn put_remote_object(&self, id: &str, object: Value) {
+        let mut inner = self.write();
+        inner.add_reply(object["inReplyTo"].as_str(), id);
+        inner.remote_objects.insert(id.to_string(), object);
+    }
+
+    pub fn get_remote_object(&self, id: &str) -> Option<Value> {
+        self.read().remote_objects.get(id).cloned()
+    }
+
+    /// Drop a cached remote object, returning whether anything was removed.
+    pub fn purge_remote_object(&self, id: &str) -> bool {
+        let mut inner = self.write();
+        match inner.remote_objects.remove(id) {
+            Some(object) => {
+                inner.remove_reply(object["inReplyTo"].as_str(), id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn put_remote_actor(&self, actor: RemoteActor) {
+        self.write().remote_actors.insert(actor.id.clone(), actor);
+    }
+
+    pub fn get_remote_actor(&self, id: &str) -> Option<RemoteActor> {
+        self.read().remote_actors.get(id).cloned()
+    }
+
+    /// Ids of the remote actors due to be fetched again.
+    pub fn stale_remote_actors(&self, now: DateTime<Utc>) -> Vec<String> {
+        self.read()
+            .remote_actors
+            .values()
+            .filter(|actor| actor.is_stale(now))
+            .map(|actor| actor.id.clone())
+            .collect()
+    }
+
+    /// Record an interaction, returning `false` if the actor had already
+    /// interacted with the object in that way.
+    pub fn add_interaction(
+        &self,
+        kind: Interaction,
+        object_id: &str,
+        actor_id: &str,
+        activity_id: &str,
+    ) -> bool {
+        let mut inner = self.write();
+        let actors = inner
+            .interactions
+            .entry(kind)
+            .or_default()
+            .entry(object_id.to_string())
+            .or_default();
+        if actors.contains_key(actor_id) {
+            return false;
+        }
+        actors.insert(actor_id.to_string(), activity_id.to_string());
+        true
+    }
+
+    /// Remove an actor's interaction with an object, returning the id of the
+    /// activity that made it.
+    pub fn remove_interaction(
+        &self,
+        kind: Interaction,
+        object_id: &str,
+        actor_id: &str,
+    ) -> Option<String> {
+        self.write()
+            .interactions
+            .get_mut(&kind)?
+            .get_mut(object_id)?
+            .remove(actor_id)
+    }
+
+    /// Remove an interaction by the id of its activity, provided it was made
+    /// by `actor_id`. Returns what kind of interaction it was and with which
+    /// object.
+    pub fn remove_interaction_activity(
+        &self,
+        actor_id: &str,
+        activity_id: &str,
+    ) -> Option<(Interaction, String)> {
+        let mut inner = self.write();
+        for (kind, objects) in inner.interactions.iter_mut() {
+            let found = objects
+                .iter_mut()
+                .find(|(_, actors)| actors.get(actor_id).map(String::as_str) == Some(activity_id));
+            if let Some((object_id, actors)) = found {
+                actors.remove(actor_id);
+                return Some((*kind, object_id.clone()));
+            }
+        }
+        None
+    }
+
+    /// Ids of the actors who interacted with an object in the given way.
+    pub fn interactions(&self, kind: Interaction, object_id: &str) -> Vec<String> {
+        self.read()
+            .interactions
+            .get(&kind)
+            .and_then(|objects| objects.get(object_id))
+            .map(|actors| actors.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Record a vote, returning `false` if it repeats an earlier one or, for
+    /// single-choice polls, if the voter has already chosen an option.
+    pub fn add_vote(
+        &self,
+        question_id: &str,
+        voter_id: &str,
+        option: &str,
+        multiple: bool,
+    ) -> bool {
+        let mut inner = self.write();
+        let choices = inner
+            .votes
+            .entry(question_id.to_string())
+            .or_default()
+            .entry(voter_id.to_string())
+            .or_default();
+        if !multiple && !choices.is_empty() {
+            return false;
+        }
+        choices.insert(option.to_string())
+    }
+
+    /// The options each voter chose on a poll, keyed by voter id.
+    pub fn votes(&self, question_id: &str) -> BTreeMap<String, BTreeSet<String>> {
+        self.read()
+            .votes
+            .get(question_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn push_outbox(&self, actor_name: &str, activity: Value) {
+        self.write()
+            .outbox
+            .entry(actor_name.to_string())
+            .or_default()
+            .push(activity);
+    }
+
+    /// Remove an activity from an actor's outbox, returning it.
+    pub fn remove_outbox(&self, actor_name: &str, activity_id: &str) -> Option<Value> {
+        let mut inner = self.write();
+        let activities = inner.outbox.get_mut(actor_name)?;
+        let index = activities
+            .iter()
+            .position(|activity| activity["id"].as_str() == Some(activity_id))?;
+        Some(activities.remove(index))
+    }
+
+    /// Activities in an actor's outbox, newest first.
+    pub fn outbox(&self, actor_name: &str) -> Vec<Value> {
+        self.read()
+            .outbox
+            .get(actor_name)
+            .map(|activities| activities.iter().rev().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// When a local actor last published anything, going by its outbox.
+    pub fn last_published(&self, actor_name: &str) -> Option<DateTime<Utc>> {
+        self.read()
+            .outbox
+            .get(actor_name)?
+            .iter()
+            .rev()
+            .find_map(|activity| activity["published"].as_str())
+            .and_then(|published| DateTime::parse_from_rfc3339(published).ok())
+            .map(|published| published.with_timezone(&Utc))
+    }
+
+    /// Deleted local actors, by name, with their tombstones.
+    pub fn deleted_actors(&self) -> BTreeMap<String, ObjectTombstone> {
+        self.read()
+            .actors
+            .iter()
+            .filter_map(|(name, status)| match status {
+                ActorStatus::Deleted(tombstone) => Some((name.clone(), tombstone.clone())),
+                ActorStatus::Active { .. } => None,
+            })
+            .collect()
+    }
+
+    /// Reserve the name of an actor deleted in an earlier run.
+    pub fn restore_deleted_actor(&self, name: &str, tombstone: ObjectTombstone) {
+        self.write()
+            .actors
+            .insert(name.to_string(), ActorStatus::Deleted(tombstone));
+    }
+
+    /// Names of the local actors that have not been deleted.
+    pub fn active_actors(&self) -> Vec<String> {
+        self.read()
+            .actors
+            .iter()
+            .filter(|(_, status)| matches!(status, ActorStatus::Active { .. }))
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// How many notes, articles and pages local actors have published and
+    /// not deleted.
+    pub fn local_post_count(&self) -> usize {
+        self.read()
+            .objects
+            .values()
+            .filter(|object| matches!(object, StoredObject::Note(_)))
+            .count()
+    }
+
+    pub fn put_media(&self, media_id: &str, document: ObjectDocument) {
+        self.write().media.insert(media_id.to_string(), document);
+    }
+
+    pub fn get_media(&self, media_id: &str) -> Option<ObjectDocument> {
+        self.read().media.get(media_id).cloned()
+    }
+
+    pub fn put_domain_policy(&self, policy: DomainPolicy) {
+        self.write()
+            .domain_policies
+            .insert(policy.domain.clone(), policy);
+    }
+
+    /// Drop the policy for a domain, returning whether there was one.
+    pub fn remove_domain_policy(&self, domain: &str) -> bool {
+        self.write().domain_policies.remove(domain).is_some()
+    }
+
+    pub fn domain_policies(&self) -> Vec<DomainPolicy> {
+        self.read().domain_policies.values().cloned().collect()
+    }
+
+    /// The policy for a host, set on it or on the nearest domain above it.
+    pub fn domain_policy(&self, host: &str) -> Option<DomainPolicy> {
+        let inner = self.read();
+        let mut domain = host;
+        loop {
+            if let Some(policy) = inner.domain_policies.get(domain) {
+                return Some(policy.clone());
+            }
+            domain = domain.split_once('.')?.1;
+        }
+    }
+
+    /// Allow a domain, returning `false` if it already was.
+    pub fn allow_domain(&self, domain: &str) -> bool {
+        self.write().allowed_domains.insert(domain.to_string())
+    }
+
+    /// Stop allowing a domain, returning whether it was allowed.
+    pub fn disallow_domain(&self, domain: &str) -> bool {
+        self.write().allowed_domains.remove(domain)
+    }
+
+    pub fn allowed_domains(&self) -> Vec<String> {
+        self.read().allowed_domains.iter().cloned().collect()
+    }
+
+    /// Whether a host, or a domain above it, is allowed.
+    pub fn is_allowed_domain(&self, host: &str) -> bool {
+        let inner = self.read();
+        let mut domain = host;
+        loop {
+            if inner.allowed_domains.contains(domain) {
+                return true;
+            }
+            domain = match domain.split_once('.') {
+                Some((_, parent)) => parent,
+                None => return false,
+            };
+        }
+    }
+
+    /// Ids of the known replies to an object.
+    pub fn replies(&self, id: &str) -> Vec<String> {
+        self.read()
+            .replies
+            .get(id)
+            .map(|replies| replies.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tombstone_replaces_note() {
+        let store = Store::new();
+        let note = ObjectNote::new("https://example.com/@a/notes/1", "a", None, "hi");
+        store.put_note(note.clone());
+
+        let tombstone = store
+            .tombstone_object(&note.id)
+            .expect("a stored note to be tombstoned");
+        assert_eq!(tombstone.object_type, OBJECT_TYPE_TOMBSTONE);
+        assert_eq!(tombstone.former_type, OBJECT_TYPE_NOTE);
+        assert_eq!(
+            store.get_object(&note.id),
+            Some(StoredObject::Tombstone(tombstone))
+        );
+
+        // Deleting twice does nothing
+        assert_eq!(store.tombstone_object(&note.id), None);
+        assert_eq!(store.tombstone_object("https://example.com/missing"), None);
+    }
+
+    #[test]
+    fn test_replies_follow_stored_and_deleted_objects() {
+        let store = Store::new();
+        let parent = ObjectNote::new("https://example.com/@a/notes/1", "a", None, "hi");
+        let reply = ObjectNote::new(
+            "https://example.com/@a/notes/2",
+            "a",
+            Some(&parent.id),
+            "hello",
+        );
+        store.put_note(parent.clone());
+        store.put_note(reply.clone());
+        store.put_remote_object(
+            "https://remote.example/notes/3",
+            serde_json::json!({ "id": "https://remote.example/notes/3", "inReplyTo": parent.id }),
+        );
+        assert_eq!(
+            store.replies(&parent.id),
+            vec![reply.id.clone(), "https://remote.example/notes/3".to_string()]
+        );
+
+        store.tombstone_object(&reply.id);
+        store.purge_remote_object("https://remote.example/notes/3");
+        assert!(store.replies(&parent.id).is_empty());
+    }
+
+    #[test]
+    fn test_interactions_can_be_undone_by_object_or_activity() {
+        let store = Store::new();
+        let note = "https://example.com/@a/notes/1";
+        let (b, c) = ("https://remote.example/b", "https://remote.example/c");
+        assert!(store.add_interaction(Interaction::Like, note, b, "https://remote.example/1"));
+        assert!(!store.add_interaction(Interaction::Like, note, b, "https://remote.example/2"));
+        assert!(store.add_interaction(Interaction::Like, note, c, "https://remote.example/3"));
+        assert!(store.add_interaction(Interaction::Announce, note, b, "https://remote.example/4"));
+        assert_eq!(store.interactions(Interaction::Like, note).len(), 2);
+        assert_eq!(store.interactions(Interaction::Announce, note).len(), 1);
+
+        // Only the actor who made an interaction can undo it
+        assert_eq!(
+            store.remove_interaction_activity(b, "https://remote.example/3"),
+            None
+        );
+        assert_eq!(
+            store.remove_interaction_activity(c, "https://remote.example/3"),
+            Some((Interaction::Like, note.to_string()))
+        );
+        assert_eq!(
+            store.remove_interaction(Interaction::Like, note, b),
+            Some("https://remote.example/1".to_string())
+        );
+        assert!(store.interactions(Interaction::Like, note).is_empty());
+        assert_eq!(store.interactions(Interaction::Announce, note), vec![b]);
+    }
+
+    #[test]
+    fn test_single_choice_polls_take_one_vote_per_voter() {
+        let store = Store::new();
+        let (poll, voter) = ("https://example.com/@a/notes/1", "https://remote.example/b");
+        assert!(store.add_vote(poll, voter, "yes", false));
+        assert!(!store.add_vote(poll, voter, "no", false));
+        assert!(store.add_vote(poll, "https://remote.example/c", "no", false));
+
+        let multiple = "https://example.com/@a/notes/2";
+        assert!(store.add_vote(multiple, voter, "yes", true));
+        assert!(store.add_vote(multiple, voter, "no", true));
+        assert!(!store.add_vote(multiple, voter, "no", true));
+
+        assert_eq!(store.votes(poll).len(), 2);
+        assert_eq!(store.votes(multiple)[voter].len(), 2);
+    }
+
+    #[test]
+    fn test_deleted_actor_name_stays_reserved() {
+        let store = Store::new();
+        assert_eq!(
+            store.register_actor("a", ACTOR_TYPE_APPLICATION, "example.com"),
+            Ok(())
+        );
+        assert_eq!(
+            store.register_actor("a", ACTOR_TYPE_APPLICATION, "example.com"),
+            Err(RegisterError::Taken)
+        );
+
+        let tombstone = ObjectTombstone::new("https://example.com/@a", "Person");
+        assert!(store.delete_actor("a", tombstone.clone()));
+        assert!(!store.delete_actor("a", tombstone.clone()));
+        assert_eq!(store.actor_status("a"), Some(ActorStatus::Deleted(tombstone)));
+        assert_eq!(
+            store.register_actor("a", ACTOR_TYPE_APPLICATION, "example.com"),
+            Err(RegisterError::Reserved)
+        );
+    }
+}
@@ -1,92 +1,850 @@
-use actix_web::{get, web, HttpResponse, Responder};
+use actix_web::http::{header, StatusCode};
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use serde_json;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use url::{form_urlencoded, Url};
 
-use crate::actors::actor_lookup;
+use crate::actors::{self, actor_lookup, LocalActorPerson};
+use crate::app::AppState;
 use crate::config;
 use crate::constants::*;
+use crate::content::escape_html;
+use crate::fetch;
+use crate::html;
+use crate::jsonld;
+use crate::objects;
 
-// use log::{info};
-use std::env;
+use log::info;
 
-use std::fs;
-use std::fs::File;
-use std::io::prelude::*;
+/// Serve WebFinger as RFC 7033 asks: every response, errors included, is
+/// JSON with the JRD media type and open to cross-origin requests.
+#[get("/.well-known/webfinger")]
+pub async fn resolver_service(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
+    // Parsed by hand, as `rel` may be given more than once.
+    let params = WebfingerParams::parse(req.query_string());
+    let result = match params.resource.as_deref() {
+        None | Some("") => Err(ResolverError::MissingResource),
+        Some(resource) => resolver(&data, resource),
+    };
+
+    let status = match &result {
+        Err(err) => err.status(),
+        Ok(_) => StatusCode::OK,
+    };
+    let body = match result {
+        Err(err) => serde_json::to_string_pretty(&json!({ "error": err.to_string() })).unwrap(),
+        Ok(mut result) => {
+            result.filter_rels(&params.rels);
+            serde_json::to_string_pretty(&result).unwrap()
+        }
+    };
+    HttpResponse::build(status)
+        .insert_header((header::CONTENT_TYPE, MEDIA_TYPE_JRD_JSON))
+        .insert_header((header::ACCESS_CONTROL_ALLOW_ORIGIN, "*"))
+        .body(body)
+}
 
-use std::io::Write;
+/// The host-meta XRD document, pointing at our WebFinger route.
+#[get("/.well-known/host-meta")]
+pub async fn host_meta_service(req: HttpRequest) -> impl Responder {
+    HttpResponse::Ok()
+        .insert_header((header::CONTENT_TYPE, MEDIA_TYPE_XRD_XML))
+        .insert_header((header::ACCESS_CONTROL_ALLOW_ORIGIN, "*"))
+        .body(host_meta_xml(&webfinger_template(&req)))
+}
 
-use log::{debug, error, log_enabled, info, Level};
+/// The JSON form of host-meta, from RFC 6415.
+#[get("/.well-known/host-meta.json")]
+pub async fn host_meta_json_service(req: HttpRequest) -> impl Responder {
+    let document = json!({
+        "links": [{ "rel": "lrdd", "template": webfinger_template(&req) }]
+    });
+    HttpResponse::Ok()
+        .insert_header((header::CONTENT_TYPE, MEDIA_TYPE_JRD_JSON))
+        .insert_header((header::ACCESS_CONTROL_ALLOW_ORIGIN, "*"))
+        .body(serde_json::to_string_pretty(&document).unwrap())
+}
 
-// env_logger::init();
+/// The LRDD template for the hosted domain a request was made to. Requests
+/// to an account domain in a split-domain setup are pointed at the server
+/// actually running WebFinger.
+fn webfinger_template(req: &HttpRequest) -> String {
+    let connection = req.connection_info();
+    let hosted = config::CONFIG
+        .hosted_domain(connection.host())
+        .unwrap_or_else(|| config::CONFIG.primary_domain());
+    format!("{}/.well-known/webfinger?resource={{uri}}", hosted.base_url)
+}
 
-#[get("/.well-known/webfinger")]
-pub async fn resolver_service(info: web::Query<WebfingerParams>) -> impl Responder {
-    match resolver(&info.resource) {
-        Err(_err) => HttpResponse::NotFound().finish(),
-        Ok(result) => HttpResponse::Ok().body(serde_json::to_string_pretty(&result).unwrap()),
+fn host_meta_xml(template: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<XRD xmlns="http://docs.oasis-open.org/ns/xri/xrd-1.0">
+  <Link rel="lrdd" type="{}" template="{}"/>
+</XRD>
+"#,
+        MEDIA_TYPE_JRD_JSON,
+        escape_html(template)
+    )
+}
+
+/// Where the OStatus subscribe template sends people who want to follow or
+/// reply to `uri` from an account here. There is no web interface to do that
+/// from, so they get a page describing the object instead. Remote objects are
+/// looked up with the same limits as any other fetch; whatever can't be found
+/// that way is not found here either.
+#[get("/authorize_interaction")]
+pub async fn authorize_interaction_service(
+    query: web::Query<InteractionParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    match interaction_object(&data, &query.uri).await {
+        None => HttpResponse::NotFound().finish(),
+        Some(object) => HttpResponse::Ok()
+            .content_type(MEDIA_TYPE_HTML)
+            .body(html::interaction_page(&object)),
     }
 }
 
-pub fn resolver(resource: &String) -> Result<WebfingerResult, ResolverError> {
+async fn interaction_object(data: &AppState, uri: &str) -> Option<Value> {
+    if let Some(actor) = actors::local_actor_by_id(data, uri) {
+        // Only what the page shows; the full actor object carries the key.
+        return Some(json!({
+            "id": actor.actor_id(),
+            "type": actor.actor_type,
+            "name": actor.name,
+            "summary": actor.profile.summary,
+            "url": actor.actor_html_url(),
+        }));
+    }
+    if let Some(object) = objects::object_value(data, uri) {
+        // The page is for anyone; followers-only and direct posts aren't.
+        return Some(object).filter(jsonld::is_addressed_to_public);
+    }
+    fetch::fetch_object(uri).await.ok()
+}
 
-    info!("something");
+/// Resolve an `acct:user@domain` resource, or the id or profile URL of one of
+/// our actors, to the actor's WebFinger document.
+pub fn resolver(data: &AppState, resource: &str) -> Result<WebfingerResult, ResolverError> {
+    info!("Resolving {}", resource);
 
     let mut parsed_query = resource.splitn(2, ':');
     let res_prefix = parsed_query.next().ok_or(ResolverError::InvalidResource)?;
-    if res_prefix != "acct" {
-        return Err(ResolverError::InvalidResource);
+    let actor = match res_prefix {
+        "acct" => {
+            let res = parsed_query.next().ok_or(ResolverError::InvalidResource)?;
+            account_lookup(data, res)?
+        }
+        "https" | "http" => url_lookup(data, resource)?,
+        _ => return Err(ResolverError::InvalidResource),
+    };
+
+    let mut aliases = vec![actor.actor_id()];
+    if actor.actor_html_url() != actor.actor_id() {
+        aliases.push(actor.actor_html_url());
     }
-    let res = parsed_query.next().ok_or(ResolverError::InvalidResource)?;
-    let mut parsed_res = res.splitn(2, '@');
+    Ok(WebfingerResult {
+        subject: format!("acct:{}", actor.handle()),
+        aliases,
+        links: vec![
+            WebfingerLink::new(
+                WEBFINGER_ACTOR_REL,
+                WEBFINGER_ACTOR_MEDIA_TYPE,
+                &actor.actor_id(),
+            ),
+            WebfingerLink::new(
+                WEBFINGER_PROFILE_PAGE_REL,
+                "text/html",
+                &actor.actor_html_url(),
+            ),
+            WebfingerLink {
+                rel: WEBFINGER_SUBSCRIBE_REL.to_string(),
+                mime_type: None,
+                href: None,
+                template: Some(format!(
+                    "{}/authorize_interaction?uri={{uri}}",
+                    actor.hosted_domain().base_url
+                )),
+            },
+        ],
+    })
+}
+
+fn account_lookup(data: &AppState, account: &str) -> Result<LocalActorPerson, ResolverError> {
+    let mut parsed_res = account.splitn(2, '@');
     let user = parsed_res.next().ok_or(ResolverError::InvalidResource)?;
     let domain = parsed_res.next().ok_or(ResolverError::InvalidResource)?;
-    if domain != config::CONFIG.domain {
-        // TODO: match multiple domains?
-        return Err(ResolverError::WrongDomain);
-    }
-
-    match actor_lookup(&user.to_string()) {
-        Err(_err) => Err(ResolverError::NotFound),
-        Ok(actor) => Ok(WebfingerResult {
-            subject: resource.clone(),
-            links: vec![WebfingerLink {
-                rel: WEBFINGER_ACTOR_REL.to_string(),
-                mime_type: WEBFINGER_ACTOR_MEDIA_TYPE.to_string(),
-                href: actor.actor_id(),
-            }],
+    if user.is_empty() {
+        return Err(ResolverError::InvalidResource);
+    }
+    // Split-domain accounts may be asked for under the host actors are
+    // served from as well as their account domain.
+    let hosted = config::CONFIG
+        .hosted_domain(domain)
+        .ok_or(ResolverError::WrongDomain)?;
+
+    match actor_lookup(data, user) {
+        Err(actors::ResolverError::NotFound) => Err(ResolverError::NotFound),
+        Err(actors::ResolverError::Gone(_)) => Err(ResolverError::Gone),
+        Ok(actor) if actor.domain != hosted.domain => Err(ResolverError::NotFound),
+        Ok(actor) => Ok(actor),
+    }
+}
+
+/// Find the actor whose id or profile URL is `url`.
+fn url_lookup(data: &AppState, url: &str) -> Result<LocalActorPerson, ResolverError> {
+    let parsed = Url::parse(url).map_err(|_| ResolverError::InvalidResource)?;
+    let host = match (parsed.host_str(), parsed.port()) {
+        (Some(host), None) => host.to_string(),
+        (Some(host), Some(port)) => format!("{}:{}", host, port),
+        (None, _) => return Err(ResolverError::InvalidResource),
+    };
+    config::CONFIG
+        .hosted_domain(&host)
+        .ok_or(ResolverError::WrongDomain)?;
+
+    if let Some(actor) = actors::local_actor_by_id(data, url) {
+        return Ok(actor);
+    }
+    // Deleted actors no longer resolve by id, but we still know them by name.
+    let name = parsed
+        .path()
+        .strip_prefix("/@")
+        .map(|path| path.strip_suffix("/actor.json").unwrap_or(path))
+        .filter(|name| !name.is_empty() && !name.contains('/'))
+        .ok_or(ResolverError::NotFound)?;
+    match actor_lookup(data, name) {
+        Err(actors::ResolverError::Gone(_)) => Err(ResolverError::Gone),
+        _ => Err(ResolverError::NotFound),
+    }
+}
+
+/// How long a resolved handle is remembered by default.
+pub const LOOKUP_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Most handles remembered at once. When full, expired answers are dropped
+/// first, then the oldest.
+const MAX_CACHED_LOOKUPS: usize = 10_000;
+
+/// Resolves handles on other servers to the ids of their ActivityPub actors,
+/// remembering answers for a while.
+pub struct WebfingerClient {
+    http: reqwest::Client,
+    ttl: Duration,
+    /// Actor ids keyed by `user@domain`, with when they were resolved.
+    cache: Mutex<HashMap<String, (Instant, String)>>,
+}
+
+impl WebfingerClient {
+    pub fn new(ttl: Duration) -> Self {
+        WebfingerClient {
+            http: fetch::CLIENT.clone(),
+            ttl,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Resolve a handle, given as `user@domain`, `@user@domain` or
+    /// `acct:user@domain`, by querying the WebFinger endpoint of its domain.
+    /// Domains serving WebFinger elsewhere are found through the LRDD
+    /// template in their host-meta.
+    pub async fn lookup(&self, handle: &str) -> Result<String, LookupError> {
+        let (user, domain) = parse_handle(handle)?;
+        let account = format!("{}@{}", user, domain.to_lowercase());
+        if let Some(actor_id) = self.cached(&account) {
+            return Ok(actor_id);
+        }
+
+        let resource = format!("acct:{}", account);
+        let url = format!(
+            "{}://{}/.well-known/webfinger?resource={}",
+            *config::PROTOCOL,
+            domain,
+            encode(&resource)
+        );
+        let result = match self.get_jrd(&url).await {
+            Ok(result) => result,
+            Err(err) => {
+                let template = self.lrdd_template(domain).await.map_err(|_| err)?;
+                self.get_jrd(&template.replace("{uri}", &encode(&resource)))
+                    .await?
+            }
+        };
+
+        let actor_id = actor_link(&result).ok_or(LookupError::NoActor)?;
+        self.remember(account, actor_id.clone());
+        Ok(actor_id)
+    }
+
+    fn remember(&self, account: String, actor_id: String) {
+        let mut cache = self
+            .cache
+            .lock()
+            .expect("webfinger cache lock should not be poisoned");
+        if cache.len() >= MAX_CACHED_LOOKUPS && !cache.contains_key(&account) {
+            cache.retain(|_, (resolved, _)| resolved.elapsed() < self.ttl);
+            if cache.len() >= MAX_CACHED_LOOKUPS {
+                let oldest = cache
+                    .iter()
+                    .min_by_key(|(_, (resolved, _))| *resolved)
+                    .map(|(account, _)| account.clone());
+                if let Some(oldest) = oldest {
+                    cache.remove(&oldest);
+                }
+            }
+        }
+        cache.insert(account, (Instant::now(), actor_id));
+    }
+
+    fn cached(&self, account: &str) -> Option<String> {
+        let mut cache = self
+            .cache
+            .lock()
+            .expect("webfinger cache lock should not be poisoned");
+        match cache.get(account) {
+            Some((resolved, actor_id)) if resolved.elapsed() < self.ttl => Some(actor_id.clone()),
+            Some(_) => {
+                cache.remove(account);
+                None
+            }
+            None => None,
+        }
+    }
+
+    async fn get_jrd(&self, url: &str) -> Result<WebfingerResult, LookupError> {
+        self.http
+            .get(url)
+            .header("accept", MEDIA_TYPE_JRD_JSON)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|_| LookupError::RequestFailed)?
+            .json::<WebfingerResult>()
+            .await
+            .map_err(|_| LookupError::InvalidResponse)
+    }
+
+    async fn lrdd_template(&self, domain: &str) -> Result<String, LookupError> {
+        let url = format!("{}://{}/.well-known/host-meta", *config::PROTOCOL, domain);
+        let xrd = self
+            .http
+            .get(&url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|_| LookupError::RequestFailed)?
+            .text()
+            .await
+            .map_err(|_| LookupError::InvalidResponse)?;
+        lrdd_template(&xrd).ok_or(LookupError::InvalidResponse)
+    }
+}
+
+impl Default for WebfingerClient {
+    fn default() -> Self {
+        Self::new(LOOKUP_CACHE_TTL)
+    }
+}
+
+/// Split a handle into its user and domain, allowing a leading `@` or
+/// `acct:`.
+pub fn parse_handle(handle: &str) -> Result<(&str, &str), LookupError> {
+    let handle = handle.trim();
+    let handle = handle
+        .strip_prefix("acct:")
+        .or_else(|| handle.strip_prefix('@'))
+        .unwrap_or(handle);
+    match handle.rsplit_once('@') {
+        Some((user, domain))
+            if !user.is_empty() && !domain.is_empty() && !domain.contains(['/', '?', '#']) =>
+        {
+            Ok((user, domain))
+        }
+        _ => Err(LookupError::InvalidHandle),
+    }
+}
+
+/// The id of the actor a WebFinger document links to: the `self` link with an
+/// ActivityPub media type.
+fn actor_link(result: &WebfingerResult) -> Option<String> {
+    result
+        .links
+        .iter()
+        .find(|link| {
+            link.rel == WEBFINGER_ACTOR_REL
+                && link
+                    .mime_type
+                    .as_deref()
+                    .is_some_and(is_activitypub_media_type)
+        })
+        .and_then(|link| link.href.clone())
+}
+
+fn is_activitypub_media_type(media_type: &str) -> bool {
+    let mut params = media_type.split(';').map(str::trim);
+    match params.next() {
+        Some("application/activity+json") => true,
+        Some("application/ld+json") => params.any(|param| {
+            param.trim_start_matches("profile=").trim_matches('"') == CONTEXT_ACTIVITYSTREAMS
         }),
+        _ => false,
     }
 }
 
+/// The LRDD template in a host-meta XRD document.
+fn lrdd_template(xrd: &str) -> Option<String> {
+    LINK.captures_iter(xrd).find_map(|link| {
+        let attributes = &link[1];
+        let attribute = |name: &str| {
+            ATTRIBUTE
+                .captures_iter(attributes)
+                .find(|attribute| &attribute[1] == name)
+                .and_then(|attribute| attribute.get(2).or_else(|| attribute.get(3)))
+                .map(|value| value.as_str().to_string())
+        };
+        match attribute("rel").as_deref() {
+            Some("lrdd") => attribute("template").map(|template| template.replace("&amp;", "&")),
+            _ => None,
+        }
+    })
+}
+
+fn encode(value: &str) -> String {
+    form_urlencoded::byte_serialize(value.as_bytes()).collect()
+}
+
+lazy_static! {
+    static ref LINK: Regex = Regex::new(r"<Link\b([^>]*)/?>").unwrap();
+    static ref ATTRIBUTE: Regex = Regex::new(r#"(\w+)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap();
+}
+
 /// Query parameters for webfinger resolver service
-#[derive(Deserialize)]
+#[derive(Debug, Default, PartialEq)]
 pub struct WebfingerParams {
-    resource: String,
+    resource: Option<String>,
+    /// Link relations to limit the result to, all of them if empty
+    rels: Vec<String>,
+}
+
+impl WebfingerParams {
+    pub fn parse(query: &str) -> Self {
+        let mut params = WebfingerParams::default();
+        for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+            match key.as_ref() {
+                "resource" => params.resource = Some(value.into_owned()),
+                "rel" => params.rels.push(value.into_owned()),
+                _ => (),
+            }
+        }
+        params
+    }
+}
+
+/// Query parameters for the subscribe template
+#[derive(Deserialize)]
+pub struct InteractionParams {
+    uri: String,
 }
 
 /// WebFinger result that may serialized or deserialized to JSON
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct WebfingerResult {
     pub subject: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    #[serde(default)]
     pub links: Vec<WebfingerLink>,
 }
 
+impl WebfingerResult {
+    /// Keep only the links with one of the given relations. No relations
+    /// means no filtering.
+    pub fn filter_rels(&mut self, rels: &[String]) {
+        if !rels.is_empty() {
+            self.links.retain(|link| rels.contains(&link.rel));
+        }
+    }
+}
+
 /// Structure to represent a WebFinger link
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct WebfingerLink {
     pub rel: String,
-    #[serde(rename = "type")]
-    pub mime_type: String,
-    pub href: String,
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub href: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+}
+
+impl WebfingerLink {
+    pub fn new(rel: &str, mime_type: &str, href: &str) -> Self {
+        WebfingerLink {
+            rel: rel.to_string(),
+            mime_type: Some(mime_type.to_string()),
+            href: Some(href.to_string()),
+            template: None,
+        }
+    }
 }
 
 /// An error that occured while handling an incoming WebFinger request.
 #[derive(Debug, PartialEq)]
 pub enum ResolverError {
+    /// No resource was given.
+    MissingResource,
     /// The requested resource was not correctly formatted
     InvalidResource,
     /// The requested resource was not found.
     NotFound,
     /// The website of the resource is not the current one.
     WrongDomain,
+    /// The requested account has been deleted.
+    Gone,
+}
+
+impl ResolverError {
+    /// Malformed requests are the client's fault; resources we do not host
+    /// are simply unknown to us.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ResolverError::MissingResource | ResolverError::InvalidResource => {
+                StatusCode::BAD_REQUEST
+            }
+            ResolverError::NotFound | ResolverError::WrongDomain => StatusCode::NOT_FOUND,
+            ResolverError::Gone => StatusCode::GONE,
+        }
+    }
+}
+
+impl fmt::Display for ResolverError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            ResolverError::MissingResource => "The resource parameter is required",
+            ResolverError::InvalidResource => {
+                "The resource must be an acct: URI or the URL of an actor"
+            }
+            ResolverError::NotFound | ResolverError::WrongDomain => "No such account here",
+            ResolverError::Gone => "The account has been deleted",
+        };
+        f.write_str(message)
+    }
+}
+
+/// An error that occured while looking up a handle on another server.
+#[derive(Debug, PartialEq)]
+pub enum LookupError {
+    /// The handle was not of the form `user@domain`.
+    InvalidHandle,
+    /// The remote server could not be reached or returned an error.
+    RequestFailed,
+    /// The response was not a WebFinger document.
+    InvalidResponse,
+    /// The document has no link to an ActivityPub actor.
+    NoActor,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delivery::DeliveryQueue;
+    use crate::objects::ObjectTombstone;
+    use crate::store::Store;
+    use actix_web::{test as actix_test, App};
+    use serde_json::Value;
+    use std::sync::Arc;
+
+    async fn get(resource: Option<&str>) -> (StatusCode, Value) {
+        let store = Store::new();
+        store
+            .register_actor("a", ACTOR_TYPE_APPLICATION, &config::CONFIG.domain)
+            .unwrap();
+        store
+            .register_actor("b", ACTOR_TYPE_APPLICATION, &config::CONFIG.domain)
+            .unwrap();
+        store.delete_actor(
+            "b",
+            ObjectTombstone::new("https://example.com/@b", "Person"),
+        );
+        let data = AppState {
+            public_key: String::new(),
+            store: Arc::new(store),
+            delivery: DeliveryQueue::start(),
+            webfinger: Arc::new(WebfingerClient::default()),
+        };
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(web::Data::new(data))
+                .service(resolver_service),
+        )
+        .await;
+
+        let uri = match resource {
+            None => "/.well-known/webfinger".to_string(),
+            Some(resource) => format!(
+                "/.well-known/webfinger?{}",
+                form_urlencoded::Serializer::new(String::new())
+                    .append_pair("resource", resource)
+                    .finish()
+            ),
+        };
+        let response =
+            actix_test::call_service(&app, actix_test::TestRequest::get().uri(&uri).to_request())
+                .await;
+        let headers = response.headers();
+        assert_eq!(
+            headers.get(header::CONTENT_TYPE).unwrap(),
+            MEDIA_TYPE_JRD_JSON
+        );
+        assert_eq!(
+            headers.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+            "*"
+        );
+        let status = response.status();
+        let body: Value = serde_json::from_slice(&actix_test::read_body(response).await).unwrap();
+        (status, body)
+    }
+
+    #[actix_web::test]
+    async fn test_resolver_service_statuses() {
+        let domain = &config::CONFIG.domain;
+
+        let (status, body) = get(Some(&format!("acct:a@{}", domain))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["subject"], format!("acct:a@{}", domain));
+
+        let actor_id = format!("{}/@a", config::CONFIG.base_url);
+        let (status, body) = get(Some(&actor_id)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["aliases"][0], actor_id);
+
+        for resource in [
+            None,
+            Some(""),
+            Some("a"),
+            Some("acct:a"),
+            Some("mailto:a@example.com"),
+        ] {
+            let (status, body) = get(resource).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{:?}", resource);
+            assert!(body["error"].is_string());
+        }
+
+        let (status, body) = get(Some(&format!("acct:nobody@{}", domain))).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(body["error"].is_string());
+        let (status, _) = get(Some("acct:a@elsewhere.example")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = get(Some("https://elsewhere.example/@a")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, _) = get(Some(&format!("acct:b@{}", domain))).await;
+        assert_eq!(status, StatusCode::GONE);
+    }
+
+    #[actix_web::test]
+    async fn test_authorize_interaction_never_redirects() {
+        let store = Store::new();
+        store
+            .register_actor("a", ACTOR_TYPE_APPLICATION, &config::CONFIG.domain)
+            .unwrap();
+        let data = AppState {
+            public_key: String::new(),
+            store: Arc::new(store),
+            delivery: DeliveryQueue::start(),
+            webfinger: Arc::new(WebfingerClient::default()),
+        };
+        let private = "https://elsewhere.example/notes/1";
+        data.store.put_remote_object(
+            private,
+            json!({
+                "id": private,
+                "type": "Note",
+                "to": "https://elsewhere.example/users/b/followers",
+                "content": "secret",
+            }),
+        );
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(web::Data::new(data))
+                .service(authorize_interaction_service),
+        )
+        .await;
+
+        let actor_id = format!("{}/@a", config::CONFIG.base_url);
+        for (uri, expected) in [
+            (actor_id.as_str(), StatusCode::OK),
+            (private, StatusCode::NOT_FOUND),
+            ("http://127.0.0.1:1/x", StatusCode::NOT_FOUND),
+            ("javascript:alert(1)", StatusCode::NOT_FOUND),
+            ("https://elsewhere.invalid/@a", StatusCode::NOT_FOUND),
+        ] {
+            let request = actix_test::TestRequest::get()
+                .uri(&format!(
+                    "/authorize_interaction?{}",
+                    form_urlencoded::Serializer::new(String::new())
+                        .append_pair("uri", uri)
+                        .finish()
+                ))
+                .to_request();
+            let response = actix_test::call_service(&app, request).await;
+            assert_eq!(response.status(), expected, "{}", uri);
+            assert!(response.headers().get(header::LOCATION).is_none());
+            if expected == StatusCode::OK {
+                let body = actix_test::read_body(response).await;
+                let body = std::str::from_utf8(&body).unwrap();
+                assert!(body.contains("Follow"));
+                assert!(body.contains(&actor_id));
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_handle_forms() {
+        for handle in ["a@example.com", "@a@example.com", "acct:a@example.com"] {
+            assert_eq!(parse_handle(handle), Ok(("a", "example.com")));
+        }
+        for handle in ["a", "@example.com", "a@", "a@example.com/x"] {
+            assert_eq!(parse_handle(handle), Err(LookupError::InvalidHandle));
+        }
+    }
+
+    #[test]
+    fn test_lrdd_template_from_host_meta() {
+        let xrd = r#"<?xml version="1.0" encoding="UTF-8"?>
+<XRD xmlns="http://docs.oasis-open.org/ns/xri/xrd-1.0">
+  <Link rel="other" template="https://example.com/nope?uri={uri}"/>
+  <Link template='https://ap.example.com/.well-known/webfinger?x=1&amp;resource={uri}' rel='lrdd' type="application/xrd+xml"/>
+</XRD>"#;
+        assert_eq!(
+            lrdd_template(xrd).as_deref(),
+            Some("https://ap.example.com/.well-known/webfinger?x=1&resource={uri}")
+        );
+    }
+
+    #[test]
+    fn test_host_meta_round_trips_template() {
+        let template = "https://ap.example.com/.well-known/webfinger?resource={uri}";
+        assert_eq!(
+            lrdd_template(&host_meta_xml(template)).as_deref(),
+            Some(template)
+        );
+    }
+
+    #[test]
+    fn test_actor_link_accepts_either_media_type() {
+        let mut result = WebfingerResult {
+            subject: "acct:a@example.com".to_string(),
+            aliases: vec![],
+            links: vec![
+                WebfingerLink::new(
+                    WEBFINGER_PROFILE_PAGE_REL,
+                    "text/html",
+                    "https://example.com/@a",
+                ),
+                WebfingerLink::new(
+                    WEBFINGER_ACTOR_REL,
+                    MEDIA_TYPE_LD_JSON,
+                    "https://example.com/users/a",
+                ),
+            ],
+        };
+        assert_eq!(
+            actor_link(&result).as_deref(),
+            Some("https://example.com/users/a")
+        );
+        result.links[1].mime_type = Some("application/ld+json".to_string());
+        assert_eq!(actor_link(&result), None);
+    }
+
+    #[test]
+    fn test_lookup_cache_expires() {
+        let client = WebfingerClient::new(Duration::from_secs(60));
+        let now = Instant::now();
+        client.cache.lock().unwrap().insert(
+            "a@example.com".to_string(),
+            (now, "https://example.com/@a".to_string()),
+        );
+        client.cache.lock().unwrap().insert(
+            "b@example.com".to_string(),
+            (
+                now - Duration::from_secs(61),
+                "https://example.com/@b".to_string(),
+            ),
+        );
+        assert_eq!(
+            client.cached("a@example.com").as_deref(),
+            Some("https://example.com/@a")
+        );
+        assert_eq!(client.cached("b@example.com"), None);
+        assert!(!client.cache.lock().unwrap().contains_key("b@example.com"));
+    }
+
+    #[test]
+    fn test_lookup_cache_is_bounded() {
+        let client = WebfingerClient::new(Duration::from_secs(60));
+        let now = Instant::now();
+        {
+            let mut cache = client.cache.lock().unwrap();
+            for i in 0..MAX_CACHED_LOOKUPS {
+                let age = Duration::from_secs(if i == 0 { 61 } else { 0 });
+                cache.insert(format!("{}@example.com", i), (now - age, String::new()));
+            }
+        }
+
+        // An expired answer makes room first.
+        client.remember("new@example.com".to_string(), String::new());
+        let cache = client.cache.lock().unwrap();
+        assert_eq!(cache.len(), MAX_CACHED_LOOKUPS);
+        assert!(!cache.contains_key("0@example.com"));
+        assert!(cache.contains_key("new@example.com"));
+        drop(cache);
+
+        // Otherwise the oldest goes.
+        client.remember("newer@example.com".to_string(), String::new());
+        let cache = client.cache.lock().unwrap();
+        assert_eq!(cache.len(), MAX_CACHED_LOOKUPS);
+        assert!(cache.contains_key("newer@example.com"));
+        assert!(cache.contains_key("new@example.com"));
+    }
+
+    #[test]
+    fn test_params_keep_every_rel() {
+        let params = WebfingerParams::parse(
+            "resource=acct%3Aa%40example.com&rel=self&rel=http%3A%2F%2Fwebfinger.net%2Frel%2Fprofile-page",
+        );
+        assert_eq!(params.resource.as_deref(), Some("acct:a@example.com"));
+        assert_eq!(
+            params.rels,
+            vec![WEBFINGER_ACTOR_REL, WEBFINGER_PROFILE_PAGE_REL]
+        );
+
+        let mut result = WebfingerResult {
+            subject: "acct:a@example.com".to_string(),
+            aliases: vec![],
+            links: vec![
+                WebfingerLink::new(
+                    WEBFINGER_ACTOR_REL,
+                    WEBFINGER_ACTOR_MEDIA_TYPE,
+                    "https://example.com/@a",
+                ),
+                WebfingerLink::new(
+                    WEBFINGER_PROFILE_PAGE_REL,
+                    "text/html",
+                    "https://example.com/@a",
+                ),
+                WebfingerLink::new("other", "text/html", "https://example.com/"),
+            ],
+        };
+        result.filter_rels(&params.rels);
+        assert_eq!(result.links.len(), 2);
+    }
 }